@@ -0,0 +1,302 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the [Archiver], which saves whole stories to disk.
+//!
+//! Every story is archived into its own directory, named after its id, with a stable layout:
+//!
+//! ```text
+//! <dest_dir>/<story_id>/
+//!     story.json          the story resource
+//!     chapters/<id>.json  every chapter resource, including its text
+//!     cover.<ext>         the full size cover image, if the story has one
+//! ```
+//!
+//! Files are written to a temporary name and renamed into place, so an interrupted archive
+//! never leaves a partial file behind. Archiving the same story again resumes where it left off:
+//! chapters which were already saved and have not been modified since are not fetched again.
+//! Rate limits are handled by the [RetryPolicy][crate::retry::RetryPolicy] of the client.
+
+use crate::client::Client;
+use crate::model::{Chapter, Story};
+use crate::response::Error;
+use crate::response::error::Trace;
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The errors which can occur while archiving a story.
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum ArchiveError {
+    /// A request to FimFic failed.
+    #[error("request failed: {0}")]
+    Request(#[from] Error),
+    /// Reading or writing a file failed.
+    #[error("could not write {}: {source}", path.display())]
+    Io {
+        /// The file or directory which could not be written.
+        path: PathBuf,
+        /// The underlying error.
+        source: io::Error,
+    },
+    /// A resource could not be serialized.
+    #[error("could not serialize resource: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+/// What happened while archiving a story.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveSummary {
+    /// The directory the story was archived into.
+    pub path: PathBuf,
+    /// The number of chapters fetched and written.
+    pub chapters_written: usize,
+    /// The number of chapters which were already archived and up to date.
+    pub chapters_skipped: usize,
+    /// Whether the cover image was downloaded.
+    pub cover_written: bool,
+}
+
+/// Saves stories, their chapters, and their cover art to disk.
+#[derive(Debug, Clone)]
+pub struct Archiver {
+    include_cover: bool,
+}
+
+impl Default for Archiver {
+    fn default() -> Self {
+        Archiver {
+            include_cover: true,
+        }
+    }
+}
+
+impl Archiver {
+    /// Creates an archiver which saves cover images.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether cover images are downloaded.
+    pub fn include_cover(mut self, include_cover: bool) -> Self {
+        self.include_cover = include_cover;
+        self
+    }
+
+    /// Archives the story with the given id into `dest_dir/<story_id>`, resuming a previous
+    /// archive of the same story if there is one.
+    pub async fn archive_story(&self, client: &Client, story_id: u64, dest_dir: impl AsRef<Path>) -> Result<ArchiveSummary, ArchiveError> {
+        let dir = dest_dir.as_ref().join(story_id.to_string());
+        let chapter_dir = dir.join("chapters");
+        create_dir_all(&chapter_dir).await?;
+
+        let story = client.story(story_id).await?;
+        let chapters = client.story_chapters(story_id).await?;
+
+        let mut stale = Vec::new();
+        for listed in &chapters {
+            // Paths are built from the parsed id, so the server can't name files outside `dir`.
+            let id = parse_id(&listed.id)?;
+            let path = chapter_dir.join(format!("{}.json", id));
+            if !is_up_to_date(&path, listed).await {
+                stale.push((id, path));
+            }
+        }
+
+        let skipped = chapters.len() - stale.len();
+        futures::future::try_join_all(stale.iter().map(|(id, path)| async move {
+            let chapter = client.chapter(*id).await?;
+            write_json(path, &chapter).await
+        })).await?;
+
+        let cover_written = match cover_url(&story) {
+            Some(url) if self.include_cover => {
+                let path = dir.join(format!("cover.{}", extension(url)));
+                // The cover is kept only if the previous archive has the same one.
+                let archived: Option<Story> = tokio::fs::read(dir.join("story.json")).await.ok()
+                    .and_then(|b| serde_json::from_slice(&b).ok());
+                let unchanged = archived.as_ref().and_then(cover_url) == Some(url);
+                if unchanged && exists(&path).await {
+                    false
+                } else {
+                    let image = client.download(url).await?;
+                    write_atomic(&path, &image).await?;
+                    true
+                }
+            }
+            _ => false,
+        };
+
+        // Written last, so a story.json means every chapter listed in it is archived.
+        write_json(&dir.join("story.json"), &story).await?;
+
+        Ok(ArchiveSummary {
+            path: dir,
+            chapters_written: stale.len(),
+            chapters_skipped: skipped,
+            cover_written,
+        })
+    }
+}
+
+/// Whether the chapter at `path` was archived and has not been modified since.
+async fn is_up_to_date(path: &Path, listed: &Chapter) -> bool {
+    let archived: Chapter = match tokio::fs::read(path).await.ok().and_then(|b| serde_json::from_slice(&b).ok()) {
+        Some(c) => c,
+        None => return false,
+    };
+    archived.attributes.content.is_some()
+        && archived.attributes.date_modified.is_some()
+        && archived.attributes.date_modified == listed.attributes.date_modified
+}
+
+/// Parses the id of a resource, which the API sends as a string.
+pub(crate) fn parse_id(id: &str) -> Result<u64, Error> {
+    id.parse().map_err(|_| Error::UnexpectedResponse {
+        status: StatusCode::OK,
+        reason: format!("resource id {:?} is not a number", id),
+        trace: Trace::capture(),
+    })
+}
+
+fn cover_url(story: &Story) -> Option<&str> {
+    let cover = story.attributes.cover_image.as_ref()?;
+    cover.full.as_deref()
+        .or(cover.large.as_deref())
+        .or(cover.medium.as_deref())
+}
+
+/// The file extension of the image at the given URL.
+fn extension(url: &str) -> &str {
+    let name = url.split(['?', '#']).next().unwrap_or_default()
+        .rsplit('/').next().unwrap_or_default();
+    match name.rsplit_once('.') {
+        Some((_, ext)) if !ext.is_empty() && ext.len() <= 4 && ext.chars().all(|c| c.is_ascii_alphanumeric()) => ext,
+        _ => "img",
+    }
+}
+
+async fn exists(path: &Path) -> bool {
+    tokio::fs::metadata(path).await.is_ok()
+}
+
+async fn create_dir_all(path: &Path) -> Result<(), ArchiveError> {
+    tokio::fs::create_dir_all(path).await
+        .map_err(|source| ArchiveError::Io { path: path.to_owned(), source })
+}
+
+async fn write_json(path: &Path, value: &impl Serialize) -> Result<(), ArchiveError> {
+    write_atomic(path, &serde_json::to_vec_pretty(value)?).await
+}
+
+/// Writes the file under a temporary name, then renames it into place.
+async fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), ArchiveError> {
+    let tmp = path.with_extension("part");
+    tokio::fs::write(&tmp, contents).await
+        .map_err(|source| ArchiveError::Io { path: tmp.clone(), source })?;
+    tokio::fs::rename(&tmp, path).await
+        .map_err(|source| ArchiveError::Io { path: path.to_owned(), source })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fixtures, MockClient};
+
+    const COVER: &str = "https://cdn-img.fimfiction.net/story/mock/full.png";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fimapi-archive-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn mock() -> MockClient {
+        let mock = MockClient::new();
+        mock.on_get("/stories/1", fixtures::STORY)
+            .on_get("/stories/1/chapters", fixtures::CHAPTERS)
+            .on_get("/chapters/11", fixtures::CHAPTER)
+            .on_get("/chapters/12", fixtures::SECOND_CHAPTER)
+            .on_get(COVER, "not really a png");
+        mock
+    }
+
+    #[tokio::test]
+    async fn archives_a_story() {
+        let dest = temp_dir("full");
+        let mock = mock();
+        let summary = Archiver::new().archive_story(&mock.client(), 1, &dest).await.unwrap();
+
+        let dir = dest.join("1");
+        assert_eq!(summary, ArchiveSummary { path: dir.clone(), chapters_written: 2, chapters_skipped: 0, cover_written: true });
+        let story: Story = serde_json::from_slice(&std::fs::read(dir.join("story.json")).unwrap()).unwrap();
+        assert_eq!(story.attributes.title, "The Mock Story");
+        let chapter: Chapter = serde_json::from_slice(&std::fs::read(dir.join("chapters/12.json")).unwrap()).unwrap();
+        assert_eq!(chapter.attributes.content.as_deref(), Some("And they lived [i]happily[/i] ever after."));
+        assert_eq!(std::fs::read(dir.join("cover.png")).unwrap(), b"not really a png");
+
+        std::fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_partial_archives() {
+        let dest = temp_dir("resume");
+        let mock = mock();
+        let client = mock.client();
+        Archiver::new().include_cover(false).archive_story(&client, 1, &dest).await.unwrap();
+        std::fs::remove_file(dest.join("1/chapters/12.json")).unwrap();
+
+        let before = mock.requests().len();
+        let summary = Archiver::new().include_cover(false).archive_story(&client, 1, &dest).await.unwrap();
+        assert_eq!((summary.chapters_written, summary.chapters_skipped), (1, 1));
+        let fetched: Vec<String> = mock.requests()[before..].iter().map(|r| r.path.clone()).collect();
+        assert!(fetched.contains(&"/chapters/12".to_owned()));
+        assert!(!fetched.contains(&"/chapters/11".to_owned()));
+        assert!(!dest.join("1/cover.png").exists());
+
+        std::fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_chapter_ids_which_are_not_numbers() {
+        let dest = temp_dir("ids");
+        let mock = MockClient::new();
+        mock.on_get("/stories/1", fixtures::STORY)
+            .on_get("/stories/1/chapters", fixtures::CHAPTERS.replace(r#""id": "11""#, r#""id": "../../x""#));
+        let res = Archiver::new().include_cover(false).archive_story(&mock.client(), 1, &dest).await;
+        assert!(matches!(res, Err(ArchiveError::Request(Error::UnexpectedResponse { .. }))));
+        assert!(!dest.join("x.json").exists());
+
+        std::fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replaces_changed_covers() {
+        let dest = temp_dir("cover");
+        let mock = mock();
+        let client = mock.client();
+        Archiver::new().archive_story(&client, 1, &dest).await.unwrap();
+
+        let summary = Archiver::new().archive_story(&client, 1, &dest).await.unwrap();
+        assert!(!summary.cover_written);
+
+        let mock = MockClient::new();
+        let story = fixtures::STORY.replace(COVER, "https://cdn-img.fimfiction.net/story/mock/full.png?v=2");
+        mock.on_get("/stories/1", story)
+            .on_get("/stories/1/chapters", fixtures::CHAPTERS)
+            .on_get("https://cdn-img.fimfiction.net/story/mock/full.png?v=2", "a new cover");
+        let summary = Archiver::new().archive_story(&mock.client(), 1, &dest).await.unwrap();
+        assert!(summary.cover_written);
+        assert_eq!(std::fs::read(dest.join("1/cover.png")).unwrap(), b"a new cover");
+
+        std::fs::remove_dir_all(&dest).unwrap();
+    }
+
+    #[test]
+    fn guesses_image_extensions() {
+        assert_eq!(extension(COVER), "png");
+        assert_eq!(extension("https://example.com/cover.jpeg?v=2"), "jpeg");
+        assert_eq!(extension("https://example.com/cover"), "img");
+    }
+}
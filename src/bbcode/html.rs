@@ -0,0 +1,244 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the renderer from BBCode to HTML.
+//!
+//! The output follows FimFiction's closely enough to preview a chapter before posting it: every
+//! line becomes a paragraph, block tags like `[center]` and `[quote]` break paragraphs, and
+//! links and images only keep targets which are safe to show.
+
+use super::{Element, Node, Tag};
+use crate::util::escape_html;
+
+/// Renders BBCode as HTML.
+pub fn render(bbcode: &str) -> String {
+    to_html(&super::parse(bbcode))
+}
+
+/// Renders parsed BBCode as HTML.
+pub fn to_html(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    blocks(nodes, &mut out);
+    out
+}
+
+/// Renders nodes as a sequence of blocks, wrapping runs of inline content in paragraphs.
+fn blocks(nodes: &[Node], out: &mut String) {
+    let mut paragraph = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => {
+                let mut lines = text.split('\n');
+                if let Some(first) = lines.next() {
+                    paragraph.push_str(&escape_html(first));
+                }
+                for line in lines {
+                    flush(&mut paragraph, out);
+                    paragraph.push_str(&escape_html(line));
+                }
+            }
+            Node::Element(e) if e.tag.is_block() => {
+                flush(&mut paragraph, out);
+                block(e, out);
+            }
+            Node::Element(e) => inline(e, &mut paragraph),
+        }
+    }
+    flush(&mut paragraph, out);
+}
+
+fn flush(paragraph: &mut String, out: &mut String) {
+    if !paragraph.trim().is_empty() {
+        out.push_str("<p>");
+        out.push_str(paragraph);
+        out.push_str("</p>\n");
+    }
+    paragraph.clear();
+}
+
+fn block(e: &Element, out: &mut String) {
+    let wrap = |out: &mut String, open: String, close: &str| {
+        out.push_str(&open);
+        out.push('\n');
+        blocks(&e.children, out);
+        out.push_str(close);
+        out.push('\n');
+    };
+    match &e.tag {
+        Tag::Center | Tag::Left | Tag::Right | Tag::Justify => {
+            wrap(out, format!("<div style=\"text-align: {}\">", e.tag.name()), "</div>");
+        }
+        Tag::Indent(n) => wrap(out, format!("<div style=\"margin-left: {}em\">", n.saturating_mul(2)), "</div>"),
+        Tag::Quote(source) => {
+            out.push_str("<blockquote>\n");
+            if let Some(source) = source {
+                out.push_str(&format!("<cite>{}</cite>\n", escape_html(source)));
+            }
+            blocks(&e.children, out);
+            out.push_str("</blockquote>\n");
+        }
+        Tag::Code(language) => {
+            let class = language.as_ref()
+                .map(|l| format!(" class=\"language-{}\"", escape_html(l)))
+                .unwrap_or_default();
+            out.push_str(&format!("<pre><code{}>{}</code></pre>\n", class, escape_html(&e.text())));
+        }
+        Tag::HorizontalRule => out.push_str("<hr />\n"),
+        Tag::YouTube => match youtube_id(&e.text()) {
+            Some(id) => out.push_str(&format!(
+                "<div class=\"youtube\"><iframe src=\"https://www.youtube.com/embed/{}\" allowfullscreen=\"allowfullscreen\"></iframe></div>\n",
+                id,
+            )),
+            None => out.push_str(&format!("<p>{}</p>\n", escape_html(&e.text()))),
+        },
+        _ => {
+            let mut paragraph = String::new();
+            inline(e, &mut paragraph);
+            flush(&mut paragraph, out);
+        }
+    }
+}
+
+fn inline(e: &Element, out: &mut String) {
+    let children = |out: &mut String| inline_children(&e.children, out);
+    let wrap = |out: &mut String, open: &str, close: &str| {
+        out.push_str(open);
+        children(out);
+        out.push_str(close);
+    };
+    match &e.tag {
+        Tag::Bold => wrap(out, "<b>", "</b>"),
+        Tag::Italic => wrap(out, "<i>", "</i>"),
+        Tag::Underline => wrap(out, "<u>", "</u>"),
+        Tag::Strikethrough => wrap(out, "<s>", "</s>"),
+        Tag::Superscript => wrap(out, "<sup>", "</sup>"),
+        Tag::Subscript => wrap(out, "<sub>", "</sub>"),
+        Tag::SmallCaps => wrap(out, "<span style=\"font-variant-caps: small-caps\">", "</span>"),
+        Tag::Spoiler => wrap(out, "<span class=\"spoiler\">", "</span>"),
+        Tag::Size(size) => {
+            let size = if size.chars().all(|c| c.is_ascii_digit() || c == '.') { format!("{}px", size) } else { size.clone() };
+            match css_value(&size) {
+                Some(size) => wrap(out, &format!("<span style=\"font-size: {}\">", size), "</span>"),
+                None => children(out),
+            }
+        }
+        Tag::Color(color) => match css_value(color) {
+            Some(color) => wrap(out, &format!("<span style=\"color: {}\">", color), "</span>"),
+            None => children(out),
+        },
+        Tag::Url(target) => {
+            let target = target.clone().unwrap_or_else(|| e.text());
+            match safe_url(&target) {
+                Some(href) => wrap(out, &format!("<a href=\"{}\">", escape_html(href)), "</a>"),
+                None => children(out),
+            }
+        }
+        Tag::Email(address) => {
+            let address = address.clone().unwrap_or_else(|| e.text());
+            wrap(out, &format!("<a href=\"mailto:{}\">", escape_html(address.trim())), "</a>");
+        }
+        Tag::Image => match safe_url(&e.text()) {
+            Some(src) => out.push_str(&format!("<img src=\"{}\" alt=\"\" />", escape_html(src))),
+            None => out.push_str(&escape_html(&e.text())),
+        },
+        _ => {
+            // Block tags nested inside inline ones are flattened into the paragraph.
+            let mut rendered = String::new();
+            block(e, &mut rendered);
+            out.push_str(rendered.trim_end());
+        }
+    }
+}
+
+/// Renders nodes inside an inline element, where line breaks cannot end the paragraph.
+fn inline_children(nodes: &[Node], out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(&escape_html(text).replace('\n', "<br />")),
+            Node::Element(e) => inline(e, out),
+        }
+    }
+}
+
+/// Returns the URL if it is safe to link to: web and mail links, or links relative to the site.
+pub(crate) fn safe_url(url: &str) -> Option<&str> {
+    let url = url.trim();
+    let lower = url.to_ascii_lowercase();
+    let safe = ["http://", "https://", "mailto:", "/"].iter().any(|p| lower.starts_with(p))
+        || !lower.contains(':');
+    if safe && !url.is_empty() {
+        Some(url)
+    } else {
+        None
+    }
+}
+
+/// Returns the value if it can be used in a style attribute without escaping it.
+fn css_value(value: &str) -> Option<&str> {
+    let value = value.trim();
+    let lower = value.to_ascii_lowercase();
+    let functional = lower.starts_with("rgb(") || lower.starts_with("rgba(") || lower.starts_with("hsl(");
+    let allowed = |c: char| c.is_ascii_alphanumeric() || "#.%- ".contains(c) || (functional && "(),".contains(c));
+    if !value.is_empty() && value.chars().all(allowed) {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Extracts the video id from a YouTube URL, or returns the text itself if it is an id.
+pub(crate) fn youtube_id(text: &str) -> Option<&str> {
+    let text = text.trim();
+    let id = if let Some(i) = text.find("v=") {
+        &text[i + 2..]
+    } else if let Some(i) = text.find("youtu.be/") {
+        &text[i + "youtu.be/".len()..]
+    } else if let Some(i) = text.find("/embed/") {
+        &text[i + "/embed/".len()..]
+    } else {
+        text
+    };
+    let id = id.split(['&', '?', '#', '/']).next().unwrap_or_default();
+    if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_paragraphs() {
+        assert_eq!(render("First [b]bold\nline[/b].\n\nSecond & last."),
+                   "<p>First <b>bold<br />line</b>.</p>\n<p>Second &amp; last.</p>\n");
+    }
+
+    #[test]
+    fn renders_blocks() {
+        let html = render("Intro[center][i]Centered[/i][/center][quote=Rarity]Darling[/quote][hr][code]<b>[/code]");
+        assert_eq!(html, "<p>Intro</p>\n\
+            <div style=\"text-align: center\">\n<p><i>Centered</i></p>\n</div>\n\
+            <blockquote>\n<cite>Rarity</cite>\n<p>Darling</p>\n</blockquote>\n\
+            <hr />\n\
+            <pre><code>&lt;b&gt;</code></pre>\n");
+    }
+
+    #[test]
+    fn renders_embeds() {
+        assert_eq!(render("[youtube]https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=1[/youtube]"),
+                   "<div class=\"youtube\"><iframe src=\"https://www.youtube.com/embed/dQw4w9WgXcQ\" allowfullscreen=\"allowfullscreen\"></iframe></div>\n");
+        assert_eq!(render("[img]https://x.y/a.png[/img]"), "<p><img src=\"https://x.y/a.png\" alt=\"\" /></p>\n");
+        assert_eq!(render("[size=20][color=red]x[/color][/size]"),
+                   "<p><span style=\"font-size: 20px\"><span style=\"color: red\">x</span></span></p>\n");
+    }
+
+    #[test]
+    fn drops_unsafe_targets() {
+        assert_eq!(render("[url=javascript:alert(1)]click[/url]"), "<p>click</p>\n");
+        assert_eq!(render("[url]https://x.y/?a=1&b=\"2\"[/url]"),
+                   "<p><a href=\"https://x.y/?a=1&amp;b=&quot;2&quot;\">https://x.y/?a=1&amp;b=&quot;2&quot;</a></p>\n");
+        assert_eq!(render("[color=red;background:url(x)]x[/color]"), "<p>x</p>\n");
+    }
+}
@@ -0,0 +1,760 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the importer from HTML to BBCode, for documents exported from word processors like
+//! Google Docs.
+//!
+//! Formatting is taken from both tags and inline styles, so `<b>` and
+//! `<span style="font-weight:700">` are both bold. Block elements become lines, headings become
+//! bold text, and list items become lines starting with a bullet or their number. Anything
+//! FimFiction cannot show is stripped, and every lossy conversion is reported as a [Warning].
+//!
+//! ```
+//! use fimapi::bbcode::import::{from_html, Warning};
+//!
+//! let import = from_html("<p>Hello, <span style=\"font-style:italic\">world</span>!</p><script>x()</script>");
+//! assert_eq!(import.to_bbcode(), "Hello, [i]world[/i]!");
+//! assert_eq!(import.warnings, vec![Warning::DroppedElement("script".into())]);
+//! ```
+
+use super::html::{safe_url, youtube_id};
+use super::{Node, Tag};
+use std::fmt;
+
+/// The result of importing HTML.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Import {
+    /// The imported document.
+    pub nodes: Vec<Node>,
+    /// Everything which was lost along the way, each reported once.
+    pub warnings: Vec<Warning>,
+}
+
+impl Import {
+    /// The imported document as BBCode.
+    pub fn to_bbcode(&self) -> String {
+        super::to_bbcode(&self.nodes)
+    }
+}
+
+/// Something which could not be imported as it was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// An element with no equivalent in BBCode was removed, but its content was kept.
+    UnsupportedTag(String),
+    /// An element was removed along with its content, like a script, a form, or a style sheet.
+    DroppedElement(String),
+    /// A link or image was removed because its URL cannot be used on FimFiction, like a
+    /// `javascript:` URL or an image embedded in a `data:` URL.
+    UnsafeUrl(String),
+    /// A table was flattened into lines of text.
+    FlattenedTable,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::UnsupportedTag(name) => write!(f, "<{}> is not supported, only its text was kept", name),
+            Warning::DroppedElement(name) => write!(f, "<{}> was removed along with its content", name),
+            Warning::UnsafeUrl(url) => {
+                let shown: String = url.chars().take(40).collect();
+                let ellipsis = if shown.len() < url.len() { "..." } else { "" };
+                write!(f, "the URL {}{} cannot be used and was removed", shown, ellipsis)
+            }
+            Warning::FlattenedTable => f.write_str("a table was flattened into lines of text"),
+        }
+    }
+}
+
+/// Imports HTML as BBCode. Like [super::parse], this never fails: malformed markup is kept as
+/// text, and elements which are never closed are closed at the end of the input.
+pub fn from_html(html: &str) -> Import {
+    let mut builder = Builder::default();
+    for token in tokenize(html) {
+        match token {
+            Token::Text(text) => builder.text(&decode_entities(text)),
+            Token::Open { name, attrs, self_closing } => builder.open(name, attrs, self_closing),
+            Token::Close(name) => builder.close(&name),
+        }
+    }
+    builder.finish()
+}
+
+/// Elements which never have content or a closing tag.
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements which are laid out as blocks, and so start and end a line.
+const BLOCK: &[&str] = &[
+    "address", "article", "aside", "blockquote", "caption", "center", "dd", "div", "dl", "dt",
+    "figcaption", "figure", "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "li", "main",
+    "nav", "ol", "p", "pre", "section", "table", "tr", "ul",
+];
+
+/// Elements which are removed along with their content, with a warning.
+const DROPPED: &[&str] = &[
+    "audio", "button", "canvas", "embed", "form", "iframe", "input", "math", "object", "script",
+    "select", "style", "svg", "template", "textarea", "video",
+];
+
+/// Elements which are removed along with their content, without a warning since they are never
+/// part of a document's text.
+const HIDDEN: &[&str] = &["base", "link", "meta", "noscript", "title"];
+
+/// Elements whose formatting is either converted or meaningless, so removing them loses nothing.
+const TRANSPARENT: &[&str] = &[
+    "abbr", "address", "article", "aside", "body", "caption", "dd", "div", "dl", "dt", "figcaption",
+    "figure", "footer", "head", "header", "html", "label", "main", "nav", "p", "section", "span",
+    "tbody", "tfoot", "thead", "time", "wbr",
+];
+
+/// Elements whose content is text, rather than markup.
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title"];
+
+/// An element which is still open.
+struct Frame {
+    name: String,
+    tags: Vec<Tag>,
+    children: Vec<Node>,
+}
+
+#[derive(Default)]
+struct Builder {
+    /// The open elements, innermost last. The root is created on first use.
+    stack: Vec<Frame>,
+    warnings: Vec<Warning>,
+    /// The next number of each open list, or `None` for unordered lists.
+    lists: Vec<Option<u64>>,
+    /// The name and depth of the element whose content is being skipped.
+    skipping: Option<(String, usize)>,
+    /// How many `<pre>` elements are open.
+    preformatted: usize,
+    /// Whether a line break is due before the next content.
+    pending_break: bool,
+    /// Whether the current line has content, so whitespace is significant.
+    mid_line: bool,
+    /// Whether the current table row already has a cell.
+    row_has_cell: bool,
+}
+
+impl Builder {
+    fn nodes(&mut self) -> &mut Vec<Node> {
+        if self.stack.is_empty() {
+            self.stack.push(Frame { name: String::new(), tags: Vec::new(), children: Vec::new() });
+        }
+        &mut self.stack.last_mut().expect("the stack has a root").children
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let nodes = self.nodes();
+        match nodes.last_mut() {
+            Some(Node::Text(t)) => t.push_str(text),
+            _ => nodes.push(Node::text(text)),
+        }
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// Ends the current line, dropping any whitespace at its end.
+    fn line_break(&mut self) {
+        if let Some(Node::Text(t)) = self.nodes().last_mut() {
+            t.truncate(t.trim_end_matches(' ').len());
+        }
+        self.push_text("\n");
+        self.mid_line = false;
+    }
+
+    /// Whether nothing was written yet, either in the document or in the innermost element which
+    /// becomes a tag. Line breaks are not needed there.
+    fn at_start(&self) -> bool {
+        for frame in self.stack.iter().rev() {
+            if !frame.children.is_empty() {
+                return false;
+            }
+            if !frame.tags.is_empty() {
+                return true;
+            }
+        }
+        true
+    }
+
+    /// Writes the line break which is due, if any, before new content.
+    fn flush_break(&mut self) {
+        if std::mem::replace(&mut self.pending_break, false) && !self.at_start() {
+            self.line_break();
+        }
+    }
+
+    /// Makes sure the next content starts on a new line.
+    fn block_boundary(&mut self) {
+        self.pending_break = true;
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.skipping.is_some() {
+            return;
+        }
+        if self.preformatted > 0 {
+            if text.is_empty() {
+                return;
+            }
+            self.flush_break();
+            self.push_text(text);
+            self.mid_line = !text.ends_with('\n');
+            return;
+        }
+
+        let mut collapsed = String::with_capacity(text.len());
+        for c in text.chars() {
+            if c.is_whitespace() && c != '\u{a0}' {
+                if !collapsed.ends_with(' ') {
+                    collapsed.push(' ');
+                }
+            } else {
+                collapsed.push(if c == '\u{a0}' { ' ' } else { c });
+            }
+        }
+        let text = if !self.mid_line || self.pending_break { collapsed.trim_start() } else { &collapsed };
+        if text.is_empty() {
+            return;
+        }
+        self.flush_break();
+        self.push_text(text);
+        self.mid_line = true;
+    }
+
+    fn node(&mut self, node: Node) {
+        self.flush_break();
+        self.nodes().push(node);
+        self.mid_line = true;
+    }
+
+    fn open(&mut self, name: String, attrs: Vec<(String, String)>, self_closing: bool) {
+        if let Some((skipped, depth)) = &mut self.skipping {
+            if *skipped == name && !VOID.contains(&name.as_str()) && !self_closing {
+                *depth += 1;
+            }
+            return;
+        }
+        let attr = |key: &str| attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+        let is_void = VOID.contains(&name.as_str());
+
+        match name.as_str() {
+            "br" => {
+                self.flush_break();
+                if !self.at_start() {
+                    self.line_break();
+                }
+                return;
+            }
+            "hr" => {
+                self.block_boundary();
+                self.node(Node::element(Tag::HorizontalRule, Vec::new()));
+                self.block_boundary();
+                return;
+            }
+            "img" => {
+                match attr("src").map(|src| (src, safe_url(src))) {
+                    Some((src, _)) if src.trim().to_ascii_lowercase().starts_with("data:") => {
+                        self.warn(Warning::UnsafeUrl(src.to_owned()));
+                    }
+                    Some((_, Some(src))) => self.node(Node::element(Tag::Image, vec![Node::text(src)])),
+                    Some((src, None)) => self.warn(Warning::UnsafeUrl(src.to_owned())),
+                    None => {}
+                }
+                return;
+            }
+            "iframe" => {
+                if let Some(id) = attr("src").filter(|src| src.contains("youtube")).and_then(youtube_id) {
+                    self.block_boundary();
+                    self.node(Node::element(Tag::YouTube, vec![Node::text(id)]));
+                    self.block_boundary();
+                    if !self_closing {
+                        self.skipping = Some((name, 0));
+                    }
+                    return;
+                }
+            }
+            _ => {}
+        }
+
+        let dropped = DROPPED.contains(&name.as_str());
+        if dropped || HIDDEN.contains(&name.as_str()) {
+            if dropped {
+                self.warn(Warning::DroppedElement(name.clone()));
+            }
+            if !is_void && !self_closing {
+                self.skipping = Some((name, 0));
+            }
+            return;
+        }
+        if is_void {
+            return;
+        }
+
+        // Lists and tables nest with whatever is open: a new item or cell closes the last one.
+        if matches!(name.as_str(), "li" | "p" | "tr" | "td" | "th" | "dt" | "dd")
+            && self.stack.last().is_some_and(|f| f.name == name)
+        {
+            self.close(&name);
+        }
+
+        if BLOCK.contains(&name.as_str()) {
+            self.block_boundary();
+        }
+        let tags = self.tags_for(&name, attr("style").unwrap_or_default(), &attr);
+        match name.as_str() {
+            "ul" => self.lists.push(None),
+            "ol" => self.lists.push(Some(attr("start").and_then(|s| s.trim().parse().ok()).unwrap_or(1))),
+            "pre" => self.preformatted += 1,
+            "table" => self.warn(Warning::FlattenedTable),
+            "tr" => self.row_has_cell = false,
+            _ => {}
+        }
+        if matches!(name.as_str(), "td" | "th") && std::mem::replace(&mut self.row_has_cell, true) {
+            self.text(" | ");
+        }
+        if !tags.is_empty() {
+            // Starts the line outside of the new element, rather than inside it.
+            self.flush_break();
+        }
+        self.nodes();
+        self.stack.push(Frame { name: name.clone(), tags, children: Vec::new() });
+
+        if name == "li" {
+            let depth = self.lists.len().saturating_sub(1);
+            let marker = match self.lists.last_mut() {
+                Some(Some(n)) => {
+                    *n += 1;
+                    format!("{}. ", *n - 1)
+                }
+                _ => "• ".to_owned(),
+            };
+            self.flush_break();
+            self.push_text(&"    ".repeat(depth));
+            self.push_text(&marker);
+            self.mid_line = false;
+        }
+        if self_closing {
+            self.close(&name);
+        }
+    }
+
+    /// The tags an element becomes, outermost first.
+    fn tags_for<'a>(&mut self, name: &str, style: &str, attr: &dyn Fn(&str) -> Option<&'a str>) -> Vec<Tag> {
+        let mut tags = Vec::new();
+        if self.preformatted > 0 {
+            // The content of a code block is kept as text.
+            return tags;
+        }
+
+        let mut bold = false;
+        match name {
+            "b" | "strong" => bold = true,
+            "i" | "em" | "cite" | "dfn" | "var" => tags.push(Tag::Italic),
+            "u" | "ins" => tags.push(Tag::Underline),
+            "s" | "strike" | "del" => tags.push(Tag::Strikethrough),
+            "sup" => tags.push(Tag::Superscript),
+            "sub" => tags.push(Tag::Subscript),
+            "center" => tags.push(Tag::Center),
+            "blockquote" => tags.push(Tag::Quote(None)),
+            "pre" => tags.push(Tag::Code(None)),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                match name {
+                    "h1" => tags.push(Tag::Size("2em".into())),
+                    "h2" => tags.push(Tag::Size("1.5em".into())),
+                    "h3" => tags.push(Tag::Size("1.25em".into())),
+                    _ => {}
+                }
+                bold = true;
+            }
+            "font" => {
+                if let Some(color) = attr("color").filter(|c| !c.trim().is_empty()) {
+                    tags.push(Tag::Color(color.trim().to_owned()));
+                }
+            }
+            "a" => match attr("href").map(str::trim) {
+                Some(href) if href.to_ascii_lowercase().starts_with("mailto:") => {
+                    tags.push(Tag::Email(Some(href["mailto:".len()..].to_owned())));
+                }
+                // Links within the document have nowhere to go once it is posted.
+                Some(href) if href.starts_with('#') || href.is_empty() => {}
+                Some(href) => match safe_url(href) {
+                    Some(href) => tags.push(Tag::Url(Some(href.to_owned()))),
+                    None => self.warn(Warning::UnsafeUrl(href.to_owned())),
+                },
+                None => {}
+            },
+            "ul" | "ol" | "li" | "table" | "tr" | "td" | "th" => {}
+            _ if TRANSPARENT.contains(&name) => {}
+            _ => self.warn(Warning::UnsupportedTag(name.to_owned())),
+        }
+
+        let mut alignment = None;
+        for declaration in style.split(';') {
+            let mut parts = declaration.splitn(2, ':');
+            let property = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let value = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            match property.as_str() {
+                "font-weight" => bold = match value.as_str() {
+                    "bold" | "bolder" => true,
+                    "normal" | "lighter" => false,
+                    v => v.parse::<u32>().map(|w| w >= 600).unwrap_or(bold),
+                },
+                "font-style" if (value == "italic" || value == "oblique") && !tags.contains(&Tag::Italic) => {
+                    tags.push(Tag::Italic);
+                }
+                "text-decoration" | "text-decoration-line" => {
+                    if value.contains("underline") && !tags.contains(&Tag::Underline) {
+                        tags.push(Tag::Underline);
+                    }
+                    if value.contains("line-through") && !tags.contains(&Tag::Strikethrough) {
+                        tags.push(Tag::Strikethrough);
+                    }
+                }
+                "vertical-align" => match value.as_str() {
+                    "super" if !tags.contains(&Tag::Superscript) => tags.push(Tag::Superscript),
+                    "sub" if !tags.contains(&Tag::Subscript) => tags.push(Tag::Subscript),
+                    _ => {}
+                },
+                "text-align" if BLOCK.contains(&name) => alignment = match value.as_str() {
+                    "center" => Some(Tag::Center),
+                    "right" | "end" => Some(Tag::Right),
+                    "justify" => Some(Tag::Justify),
+                    _ => None,
+                },
+                _ => {}
+            }
+        }
+
+        if bold {
+            // Headings keep their size outside of the bold tag.
+            let at = tags.iter().take_while(|t| matches!(t, Tag::Size(_))).count();
+            tags.insert(at, Tag::Bold);
+        }
+        if let Some(alignment) = alignment.filter(|a| !tags.contains(a)) {
+            tags.insert(0, alignment);
+        }
+        tags
+    }
+
+    fn close(&mut self, name: &str) {
+        if let Some((skipped, depth)) = &mut self.skipping {
+            if skipped == name {
+                if *depth == 0 {
+                    self.skipping = None;
+                } else {
+                    *depth -= 1;
+                }
+            }
+            return;
+        }
+        let open = match self.stack.iter().rposition(|f| f.name == name) {
+            Some(open) if open > 0 => open,
+            _ => return,
+        };
+        while self.stack.len() > open {
+            self.close_top();
+        }
+    }
+
+    fn close_top(&mut self) {
+        let Frame { name, tags, mut children } = match self.stack.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        match name.as_str() {
+            "ul" | "ol" => {
+                self.lists.pop();
+            }
+            "pre" => {
+                self.preformatted -= 1;
+                if let Some(Node::Text(t)) = children.last_mut() {
+                    t.truncate(t.trim_end_matches('\n').len());
+                }
+            }
+            _ => {}
+        }
+
+        if tags.is_empty() {
+            for child in children {
+                match child {
+                    Node::Text(t) => self.push_text(&t),
+                    node => self.nodes().push(node),
+                }
+            }
+        } else {
+            if let Some(Node::Text(t)) = children.last_mut() {
+                t.truncate(t.trim_end().len());
+            }
+            if !children.is_empty() {
+                let node = tags.into_iter().rev().fold(children, |children, tag| vec![Node::element(tag, children)]);
+                self.nodes().extend(node);
+            }
+        }
+        if BLOCK.contains(&name.as_str()) {
+            self.block_boundary();
+        }
+    }
+
+    fn finish(mut self) -> Import {
+        while self.stack.len() > 1 {
+            self.close_top();
+        }
+        let mut nodes = self.stack.pop().map(|f| f.children).unwrap_or_default();
+        if let Some(Node::Text(t)) = nodes.last_mut() {
+            t.truncate(t.trim_end().len());
+            if t.is_empty() {
+                nodes.pop();
+            }
+        }
+        super::neutralize(&mut nodes);
+        Import { nodes, warnings: self.warnings }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Token<'a> {
+    Text(&'a str),
+    Open { name: String, attrs: Vec<(String, String)>, self_closing: bool },
+    Close(String),
+}
+
+/// Splits HTML into text and tags. Comments, doctypes, and processing instructions are skipped.
+pub(crate) fn tokenize(html: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        if start > 0 {
+            tokens.push(Token::Text(&rest[..start]));
+        }
+        rest = &rest[start..];
+
+        let skip_to = |rest: &str, end: &str| rest.find(end).map(|i| i + end.len()).unwrap_or(rest.len());
+        if rest.starts_with("<!--") {
+            rest = &rest[skip_to(rest, "-->")..];
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = &rest[skip_to(rest, ">")..];
+        } else if let Some((token, len)) = parse_tag(rest) {
+            rest = &rest[len..];
+            if let Token::Open { name, self_closing: false, .. } = &token {
+                if RAW_TEXT.contains(&name.as_str()) {
+                    let close = format!("</{}", name);
+                    let end = super::find_ignore_case(rest, &close).unwrap_or(rest.len());
+                    let text = &rest[..end];
+                    rest = &rest[end..];
+                    tokens.push(token);
+                    if !text.is_empty() {
+                        tokens.push(Token::Text(text));
+                    }
+                    continue;
+                }
+            }
+            tokens.push(token);
+        } else {
+            tokens.push(Token::Text("<"));
+            rest = &rest[1..];
+        }
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest));
+    }
+    tokens
+}
+
+/// Parses the tag at the start of `s`, which starts with `<`, returning it and its length.
+fn parse_tag(s: &str) -> Option<(Token<'_>, usize)> {
+    let bytes = s.as_bytes();
+    let closing = bytes.get(1) == Some(&b'/');
+    let name_start = if closing { 2 } else { 1 };
+    let name_len = bytes[name_start..].iter().take_while(|b| b.is_ascii_alphanumeric()).count();
+    if name_len == 0 || !bytes[name_start].is_ascii_alphabetic() {
+        return None;
+    }
+    let name = s[name_start..name_start + name_len].to_ascii_lowercase();
+    let mut i = name_start + name_len;
+
+    if closing {
+        let end = s[i..].find('>')?;
+        return Some((Token::Close(name), i + end + 1));
+    }
+
+    let mut attrs = Vec::new();
+    let mut self_closing = false;
+    loop {
+        while bytes.get(i).is_some_and(|b| b.is_ascii_whitespace()) {
+            i += 1;
+        }
+        match bytes.get(i)? {
+            b'>' => return Some((Token::Open { name, attrs, self_closing }, i + 1)),
+            b'/' => {
+                self_closing = true;
+                i += 1;
+                continue;
+            }
+            _ => self_closing = false,
+        }
+
+        let key_len = bytes[i..].iter()
+            .take_while(|b| !b.is_ascii_whitespace() && !matches!(b, b'=' | b'>' | b'/'))
+            .count()
+            .max(1);
+        let key = s[i..i + key_len].to_ascii_lowercase();
+        i += key_len;
+        while bytes.get(i).is_some_and(|b| b.is_ascii_whitespace()) {
+            i += 1;
+        }
+        let mut value = String::new();
+        if bytes.get(i) == Some(&b'=') {
+            i += 1;
+            while bytes.get(i).is_some_and(|b| b.is_ascii_whitespace()) {
+                i += 1;
+            }
+            match bytes.get(i)? {
+                &quote @ (b'"' | b'\'') => {
+                    let len = s[i + 1..].find(quote as char)?;
+                    value = decode_entities(&s[i + 1..i + 1 + len]);
+                    i += len + 2;
+                }
+                _ => {
+                    let len = bytes[i..].iter().take_while(|b| !b.is_ascii_whitespace() && **b != b'>').count();
+                    value = decode_entities(&s[i..i + len]);
+                    i += len;
+                }
+            }
+        }
+        attrs.push((key, value));
+    }
+}
+
+/// Replaces character references with the characters they stand for. Unknown references are
+/// kept as they are.
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..].find(';')
+            .filter(|&end| end > 0 && end <= 10)
+            .and_then(|end| decode_entity(&rest[1..end + 1]).map(|c| (c, end + 2)));
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return std::char::from_u32(code);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "ndash" => '–',
+        "mdash" => '—',
+        "hellip" => '…',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "copy" => '©',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_google_docs_styles() {
+        let html = r#"<html><head><meta charset="utf-8"><style>.c1{font-weight:700}</style></head><body>
+            <b style="font-weight:normal;" id="docs-internal-guid-1">
+            <p dir="ltr" style="text-align: center;"><span style="font-weight:700;font-style:italic">Chapter&nbsp;One</span></p>
+            <p dir="ltr"><span style="font-weight:400">It was a  dark
+                and <span style="text-decoration:underline">stormy</span> night&hellip;</span></p>
+            <p dir="ltr"><span class="c1">Classes are lost.</span><br/>Next line.</p></b></body></html>"#;
+        let import = from_html(html);
+        assert_eq!(import.to_bbcode(),
+                   "[center][b][i]Chapter One[/i][/b][/center]\nIt was a dark and [u]stormy[/u] night…\nClasses are lost.\nNext line.");
+        assert_eq!(import.warnings, vec![Warning::DroppedElement("style".into())]);
+    }
+
+    #[test]
+    fn imports_blocks_and_lists() {
+        let html = "<h1>Title</h1><h4>Small</h4><blockquote><p>Quoted</p></blockquote>\
+            <pre>let x = 1;\n  <b>y</b>\n</pre><hr><ul><li>one<li>two<ol start=3><li>three</ol></ul>";
+        assert_eq!(from_html(html).to_bbcode(),
+                   "[size=2em][b]Title[/b][/size]\n[b]Small[/b]\n[quote]Quoted[/quote]\n[code]let x = 1;\n  y[/code]\n[hr]\n• one\n• two\n    3. three");
+    }
+
+    #[test]
+    fn strips_unsafe_and_unsupported_markup() {
+        let html = "<p><a href=\"javascript:alert(1)\">click</a> <a href=\"https://x.y/?a=1&amp;b=2\">site</a> \
+            <a href=\"mailto:a@x.y\">mail</a> <img src=\"data:image/png;base64,AAAA\"><img src='https://x.y/a.png'> \
+            <mark>marked</mark><form><input value=x>Form</form></p>\
+            <iframe src=\"https://www.youtube.com/embed/dQw4w9WgXcQ\"></iframe>";
+        let import = from_html(html);
+        assert_eq!(import.to_bbcode(),
+                   "click [url=https://x.y/?a=1&b=2]site[/url] [email=a@x.y]mail[/email] [img]https://x.y/a.png[/img] marked\n\
+                    [youtube]dQw4w9WgXcQ[/youtube]");
+        assert_eq!(import.warnings, vec![
+            Warning::UnsafeUrl("javascript:alert(1)".into()),
+            Warning::UnsafeUrl("data:image/png;base64,AAAA".into()),
+            Warning::UnsupportedTag("mark".into()),
+            Warning::DroppedElement("form".into()),
+        ]);
+    }
+
+    #[test]
+    fn flattens_tables() {
+        let import = from_html("<table><tr><th>A</th><th>B</th></tr><tr><td>1<td>2</table><p>After</p>");
+        assert_eq!(import.to_bbcode(), "A | B\n1 | 2\nAfter");
+        assert_eq!(import.warnings, vec![Warning::FlattenedTable]);
+    }
+
+    #[test]
+    fn bbcode_in_html_stays_text() {
+        let import = from_html("<p>Write <code>[b]bold[/b]</code> &#91;i] <a href=\"https://x.y/[1]\">x</a></p><pre>[/code]</pre>");
+        let nodes = super::super::parse(&import.to_bbcode());
+        assert_eq!(nodes.iter().filter(|n| matches!(n, Node::Element(_))).count(), 2);
+        assert_eq!(super::super::plain_text(&nodes).replace('\u{200B}', ""), "Write [b]bold[/b] [i] x\n[/code]");
+    }
+
+    #[test]
+    fn skips_raw_text_elements_in_any_case() {
+        let bbcode = from_html("<p>a</p><SCRIPT>if (a < b) { x = '<p>'; }</Script><p>b</p>").to_bbcode();
+        assert!(!bbcode.contains("if"), "{}", bbcode);
+        assert!(bbcode.contains('a') && bbcode.contains('b'));
+    }
+
+    #[test]
+    fn keeps_malformed_markup_as_text() {
+        assert_eq!(from_html("1 < 2 &unknown; &#x263A; <b>unclosed").to_bbcode(), "1 < 2 &unknown; ☺ [b]unclosed[/b]");
+    }
+}
@@ -0,0 +1,417 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the converter from BBCode to Markdown, for cross-posting to Markdown-based
+//! platforms, and with the `markdown` feature, the converter back from Markdown to BBCode, for
+//! publishing chapters written in Markdown.
+//!
+//! Every line of BBCode becomes a paragraph. Markdown has no equivalent for some tags, like
+//! `[color]`, `[size]`, and alignment, so only their content is kept. What else is kept depends
+//! on the [Flavor].
+
+use super::html::youtube_id;
+use super::{Element, Node, Tag};
+#[cfg(feature = "markdown")]
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag as MdTag};
+
+/// The dialect of Markdown to write.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+#[non_exhaustive]
+pub enum Flavor {
+    /// CommonMark, plus `~~strikethrough~~` as supported by most renderers.
+    /// Underlines and spoilers are dropped.
+    #[default]
+    CommonMark,
+    /// Discord's Markdown, which also supports `__underline__` and `||spoilers||`.
+    Discord,
+}
+
+/// Converts BBCode to CommonMark.
+pub fn render(bbcode: &str) -> String {
+    to_markdown(&super::parse(bbcode), Flavor::CommonMark)
+}
+
+/// Converts parsed BBCode to Markdown of the given flavor.
+pub fn to_markdown(nodes: &[Node], flavor: Flavor) -> String {
+    let mut out = blocks(nodes, flavor).join("\n\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+/// Converts nodes to a sequence of blocks, each a paragraph or a block tag.
+fn blocks(nodes: &[Node], flavor: Flavor) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut paragraph = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => {
+                let mut lines = text.split('\n');
+                if let Some(first) = lines.next() {
+                    paragraph.push_str(&escape(first));
+                }
+                for line in lines {
+                    flush(&mut paragraph, &mut out);
+                    paragraph.push_str(&escape(line));
+                }
+            }
+            Node::Element(e) if e.tag.is_block() => {
+                flush(&mut paragraph, &mut out);
+                out.extend(block(e, flavor));
+            }
+            Node::Element(e) => paragraph.push_str(&inline(e, flavor)),
+        }
+    }
+    flush(&mut paragraph, &mut out);
+    out
+}
+
+fn flush(paragraph: &mut String, out: &mut Vec<String>) {
+    let trimmed = paragraph.trim();
+    if !trimmed.is_empty() {
+        out.push(trimmed.to_owned());
+    }
+    paragraph.clear();
+}
+
+fn block(e: &Element, flavor: Flavor) -> Vec<String> {
+    match &e.tag {
+        Tag::Quote(source) => {
+            let mut inner = Vec::new();
+            if let Some(source) = source {
+                inner.push(format!("*{}*", escape(source)));
+            }
+            inner.extend(blocks(&e.children, flavor));
+            let quoted = inner.join("\n\n")
+                .lines()
+                .map(|l| if l.is_empty() { ">".to_owned() } else { format!("> {}", l) })
+                .collect::<Vec<_>>()
+                .join("\n");
+            vec![quoted]
+        }
+        Tag::Code(language) => {
+            let text = e.text();
+            let fence = if text.contains("```") { "~~~" } else { "```" };
+            let text = text.trim_matches('\n');
+            vec![format!("{}{}\n{}\n{}", fence, language.as_deref().unwrap_or_default(), text, fence)]
+        }
+        Tag::HorizontalRule => vec!["---".to_owned()],
+        Tag::YouTube => match youtube_id(&e.text()) {
+            Some(id) => vec![format!("<https://www.youtube.com/watch?v={}>", id)],
+            None => vec![escape(&e.text())],
+        },
+        // Alignment and indentation have no equivalent.
+        _ => blocks(&e.children, flavor),
+    }
+}
+
+fn inline(e: &Element, flavor: Flavor) -> String {
+    let content = || inline_children(&e.children, flavor);
+    match &e.tag {
+        Tag::Bold => wrap("**", &content()),
+        Tag::Italic => wrap("*", &content()),
+        Tag::Strikethrough => wrap("~~", &content()),
+        Tag::Underline if flavor == Flavor::Discord => wrap("__", &content()),
+        Tag::Spoiler if flavor == Flavor::Discord => wrap("||", &content()),
+        Tag::Url(target) => {
+            let text = content();
+            let target = target.clone().unwrap_or_else(|| e.text());
+            let target = target.trim();
+            if target.is_empty() {
+                text
+            } else if target == e.text().trim() {
+                format!("<{}>", target)
+            } else {
+                format!("[{}]({})", text, link_target(target))
+            }
+        }
+        Tag::Email(address) => {
+            let address = address.clone().unwrap_or_else(|| e.text());
+            format!("[{}](mailto:{})", content(), link_target(address.trim()))
+        }
+        Tag::Image => format!("![]({})", link_target(e.text().trim())),
+        Tag::YouTube | Tag::Code(_) | Tag::Quote(_) | Tag::HorizontalRule => block(e, flavor).join(" "),
+        _ => content(),
+    }
+}
+
+/// Converts nodes inside an inline element, where line breaks cannot end the paragraph.
+fn inline_children(nodes: &[Node], flavor: Flavor) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(&escape(text).replace('\n', "\\\n")),
+            Node::Element(e) => out.push_str(&inline(e, flavor)),
+        }
+    }
+    out
+}
+
+/// Wraps text in emphasis delimiters, keeping surrounding whitespace outside of them so the
+/// emphasis is recognized.
+fn wrap(delimiter: &str, text: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_owned();
+    }
+    let start = text.len() - text.trim_start().len();
+    let end = text.trim_end().len();
+    format!("{}{}{}{}{}", &text[..start], delimiter, trimmed, delimiter, &text[end..])
+}
+
+/// Escapes a link target so it cannot end the link early.
+fn link_target(target: &str) -> String {
+    if target.contains([' ', '(', ')']) {
+        format!("<{}>", target.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        target.to_owned()
+    }
+}
+
+/// Escapes characters which Markdown would interpret as markup.
+fn escape(text: &str) -> String {
+    text.split('\n').map(escape_line).collect::<Vec<_>>().join("\n")
+}
+
+fn escape_line(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    for c in line.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '~' | '|') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+
+    // Text which would start a heading, list, or quote.
+    let indent = out.len() - out.trim_start().len();
+    let body = &out[indent..];
+    let digits = body.chars().take_while(char::is_ascii_digit).count();
+    if body.starts_with(['#', '-', '+', '=', '>']) {
+        out.insert(indent, '\\');
+    } else if digits > 0 && body[digits..].starts_with(['.', ')']) {
+        out.insert(indent + digits, '\\');
+    }
+    out
+}
+
+/// Converts Markdown to BBCode.
+///
+/// ```
+/// let bbcode = fimapi::bbcode::markdown::to_bbcode("Some *emphasis* and a [link](https://x.y).\n\n---");
+/// assert_eq!(bbcode, "Some [i]emphasis[/i] and a [url=https://x.y]link[/url].\n\n[hr]");
+/// ```
+#[cfg(feature = "markdown")]
+pub fn to_bbcode(markdown: &str) -> String {
+    super::to_bbcode(&from_markdown(markdown))
+}
+
+/// Parses Markdown into BBCode nodes.
+///
+/// Paragraphs are separated by a blank line. Headings become bold text, larger for the top
+/// three levels, and list items become lines starting with a bullet or their number, since
+/// BBCode has no lists. Inline code and HTML are kept as text, and text which looks like a
+/// BBCode tag is kept from being read as one.
+#[cfg(feature = "markdown")]
+pub fn from_markdown(markdown: &str) -> Vec<Node> {
+    let mut stack: Vec<(Option<Tag>, Vec<Node>)> = vec![(None, Vec::new())];
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut images: Vec<String> = Vec::new();
+    // Set at the start of a list item, so its first paragraph stays on the bullet's line.
+    let mut in_new_item = false;
+
+    for event in Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH) {
+        match event {
+            Event::Start(tag) => {
+                let block = matches!(tag, MdTag::Paragraph | MdTag::Heading(_) | MdTag::BlockQuote
+                    | MdTag::CodeBlock(_) | MdTag::List(_) | MdTag::Item);
+                if block && !std::mem::replace(&mut in_new_item, false) {
+                    start_block(&mut stack, !lists.is_empty());
+                }
+                match tag {
+                    MdTag::Heading(level) => {
+                        let size = match level {
+                            1 => Some("2em"),
+                            2 => Some("1.5em"),
+                            3 => Some("1.25em"),
+                            _ => None,
+                        };
+                        if let Some(size) = size {
+                            stack.push((Some(Tag::Size(size.into())), Vec::new()));
+                        }
+                        stack.push((Some(Tag::Bold), Vec::new()));
+                    }
+                    MdTag::BlockQuote => stack.push((Some(Tag::Quote(None)), Vec::new())),
+                    MdTag::CodeBlock(kind) => {
+                        let language = match kind {
+                            CodeBlockKind::Fenced(info) => info.split_whitespace().next().map(str::to_owned),
+                            CodeBlockKind::Indented => None,
+                        };
+                        stack.push((Some(Tag::Code(language)), Vec::new()));
+                    }
+                    MdTag::List(start) => lists.push(start),
+                    MdTag::Item => {
+                        let depth = lists.len().saturating_sub(1);
+                        let marker = match lists.last_mut() {
+                            Some(Some(n)) => {
+                                *n += 1;
+                                format!("{}. ", *n - 1)
+                            }
+                            _ => "• ".to_owned(),
+                        };
+                        super::push_text(&mut stack, &"    ".repeat(depth));
+                        super::push_text(&mut stack, &marker);
+                        in_new_item = true;
+                    }
+                    MdTag::Emphasis => stack.push((Some(Tag::Italic), Vec::new())),
+                    MdTag::Strong => stack.push((Some(Tag::Bold), Vec::new())),
+                    MdTag::Strikethrough => stack.push((Some(Tag::Strikethrough), Vec::new())),
+                    MdTag::Link(_, url, _) => stack.push((Some(Tag::Url(Some(url.into_string()))), Vec::new())),
+                    MdTag::Image(_, url, _) => {
+                        images.push(url.into_string());
+                        stack.push((Some(Tag::Image), Vec::new()));
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(tag) => match tag {
+                MdTag::Heading(level) => {
+                    super::close_top(&mut stack);
+                    if level <= 3 {
+                        super::close_top(&mut stack);
+                    }
+                }
+                MdTag::BlockQuote | MdTag::CodeBlock(_) => {
+                    trim_trailing_newlines(&mut stack.last_mut().expect("the block is open").1);
+                    super::close_top(&mut stack);
+                }
+                MdTag::List(_) => {
+                    lists.pop();
+                }
+                MdTag::Item => in_new_item = false,
+                MdTag::Emphasis | MdTag::Strong | MdTag::Strikethrough | MdTag::Link(..) => super::close_top(&mut stack),
+                MdTag::Image(..) => {
+                    // The alt text has nowhere to go; the image tag only holds the URL.
+                    stack.pop();
+                    let url = images.pop().unwrap_or_default();
+                    super::push_node(&mut stack, Node::element(Tag::Image, vec![Node::text(url)]));
+                }
+                _ => {}
+            },
+            Event::Text(text) | Event::Code(text) | Event::Html(text) => super::push_text(&mut stack, &text),
+            Event::SoftBreak => super::push_text(&mut stack, " "),
+            Event::HardBreak => super::push_text(&mut stack, "\n"),
+            Event::Rule => {
+                start_block(&mut stack, !lists.is_empty());
+                super::push_node(&mut stack, Node::element(Tag::HorizontalRule, Vec::new()));
+            }
+            _ => {}
+        }
+    }
+
+    while stack.len() > 1 {
+        super::close_top(&mut stack);
+    }
+    let mut nodes = stack.pop().expect("the stack always has a root").1;
+    trim_trailing_newlines(&mut nodes);
+    super::neutralize(&mut nodes);
+    nodes
+}
+
+/// Separates a new block from whatever came before it in the same parent: by a line break
+/// inside a list, and by a blank line elsewhere. Nothing is added if the previous block already
+/// ended with a separator.
+#[cfg(feature = "markdown")]
+fn start_block(stack: &mut [(Option<Tag>, Vec<Node>)], in_list: bool) {
+    match stack.last().expect("the stack always has a root").1.last() {
+        None => {}
+        Some(Node::Text(text)) if text.ends_with('\n') => {}
+        Some(_) => super::push_text(stack, if in_list { "\n" } else { "\n\n" }),
+    }
+}
+
+#[cfg(feature = "markdown")]
+fn trim_trailing_newlines(nodes: &mut Vec<Node>) {
+    if let Some(Node::Text(text)) = nodes.last_mut() {
+        text.truncate(text.trim_end_matches('\n').len());
+        if text.is_empty() {
+            nodes.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn discord(bbcode: &str) -> String {
+        to_markdown(&super::super::parse(bbcode), Flavor::Discord)
+    }
+
+    #[test]
+    fn converts_paragraphs_and_emphasis() {
+        assert_eq!(render("[b]Bold[/b] and [i] italic [/i].\nNext [s]line[/s]."),
+                   "**Bold** and  *italic* .\n\nNext ~~line~~.\n");
+        assert_eq!(render("[u]under[/u] [spoiler]secret[/spoiler] [color=red]red[/color]"), "under secret red\n");
+        assert_eq!(discord("[u]under[/u] [spoiler]secret[/spoiler]"), "__under__ ||secret||\n");
+    }
+
+    #[test]
+    fn converts_links_and_embeds() {
+        assert_eq!(render("[url=https://x.y/a_(b)]the [b]site[/b][/url] [url]https://x.y[/url]"),
+                   "[the **site**](<https://x.y/a_(b)>) <https://x.y>\n");
+        assert_eq!(render("[img]https://x.y/a.png[/img]"), "![](https://x.y/a.png)\n");
+        assert_eq!(render("[youtube]https://youtu.be/dQw4w9WgXcQ[/youtube]"), "<https://www.youtube.com/watch?v=dQw4w9WgXcQ>\n");
+    }
+
+    #[test]
+    fn converts_blocks() {
+        assert_eq!(render("Before[quote=Rarity]Darling.\nTruly.[/quote][hr][code=rust]let x = 1;[/code][center]After[/center]"),
+                   "Before\n\n> *Rarity*\n>\n> Darling.\n>\n> Truly.\n\n---\n\n```rust\nlet x = 1;\n```\n\nAfter\n");
+    }
+
+    #[test]
+    fn escapes_markup_in_text() {
+        assert_eq!(render("# not a heading\n1. not a list\n*stars* and [brackets]\n- dash"),
+                   "\\# not a heading\n\n1\\. not a list\n\n\\*stars\\* and \\[brackets\\]\n\n\\- dash\n");
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn converts_markdown_to_bbcode() {
+        assert_eq!(to_bbcode("# Title\n\nSome *emphasis*, **bold**,\nand ~~struck~~ text.  \nNew line."),
+                   "[size=2em][b]Title[/b][/size]\n\nSome [i]emphasis[/i], [b]bold[/b], and [s]struck[/s] text.\nNew line.");
+        assert_eq!(to_bbcode("A [link](https://x.y) and ![alt](https://x.y/a.png)\n\n***\n\nEnd"),
+                   "A [url=https://x.y]link[/url] and [img]https://x.y/a.png[/img]\n\n[hr]\n\nEnd");
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn converts_markdown_blocks() {
+        assert_eq!(to_bbcode("> Quoted\n>\n> twice\n\n```rust\nlet x = 1;\n```"),
+                   "[quote]Quoted\n\ntwice[/quote]\n\n[code=rust]let x = 1;[/code]");
+        assert_eq!(to_bbcode("Intro\n\n- one\n- two\n  1. nested\n\n3. three\n4. four"),
+                   "Intro\n\n• one\n• two\n    1. nested\n\n3. three\n4. four");
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn bbcode_in_markdown_stays_text() {
+        let markdown = "Index `array[b]` and [url=x] or [/i], a [link](<https://x.y/a]b>).\n\n```\n[/code][b]\n```";
+        let nodes = super::super::parse(&super::super::to_bbcode(&from_markdown(markdown)));
+
+        let tags: Vec<&Tag> = nodes.iter().filter_map(|n| match n {
+            Node::Element(e) => Some(&e.tag),
+            Node::Text(_) => None,
+        }).collect();
+        assert_eq!(tags, [&Tag::Url(Some("https://x.y/a%5Db".into())), &Tag::Code(None)]);
+        assert_eq!(super::super::plain_text(&nodes).replace('\u{200B}', ""),
+                   "Index array[b] and [url=x] or [/i], a link.\n\n[/code][b]");
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn markdown_round_trips_through_bbcode() {
+        let markdown = "**Bold** and *italic*.\n\nNext ~~line~~.\n";
+        assert_eq!(render(&to_bbcode(markdown)), markdown);
+    }
+}
@@ -0,0 +1,520 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains a parser for FimFiction-flavored BBCode, which stories, chapters, blog posts, and
+//! bios are written in.
+//!
+//! [parse] turns BBCode into a tree of [Node]s. Parsing never fails: like the site, anything
+//! which is not a well-formed tag is kept as text, and tags which are never closed are closed at
+//! the end of the input. [to_bbcode] turns a tree back into BBCode.
+//!
+//! ```
+//! use fimapi::bbcode::{parse, Node, Tag};
+//!
+//! let nodes = parse("[b]Hello[/b], [url=https://fimfiction.net]world[/url]!");
+//! assert_eq!(nodes[0], Node::element(Tag::Bold, vec![Node::text("Hello")]));
+//! assert_eq!(fimapi::bbcode::to_bbcode(&nodes), "[b]Hello[/b], [url=https://fimfiction.net]world[/url]!");
+//! ```
+
+pub mod html;
+pub mod import;
+pub mod markdown;
+
+pub use html::to_html;
+pub use import::from_html;
+pub use markdown::to_markdown;
+#[cfg(feature = "markdown")]
+pub use markdown::from_markdown;
+
+use std::fmt;
+
+/// A BBCode tag, with its argument if it takes one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Tag {
+    /// `[b]`
+    Bold,
+    /// `[i]`
+    Italic,
+    /// `[u]`
+    Underline,
+    /// `[s]`
+    Strikethrough,
+    /// `[smcaps]`
+    SmallCaps,
+    /// `[sup]`
+    Superscript,
+    /// `[sub]`
+    Subscript,
+    /// `[spoiler]`
+    Spoiler,
+    /// `[center]`
+    Center,
+    /// `[left]`
+    Left,
+    /// `[right]`
+    Right,
+    /// `[justify]`
+    Justify,
+    /// `[indent=n]`, indenting by `n` levels.
+    Indent(u32),
+    /// `[size=x]`, where `x` is a CSS size like `1.5em` or a number of pixels.
+    Size(String),
+    /// `[color=x]`, where `x` is a CSS color like `#ff0000` or `red`.
+    Color(String),
+    /// `[url]` or `[url=target]`. Without a target, the content is the target.
+    Url(Option<String>),
+    /// `[email]` or `[email=address]`. Without an address, the content is the address.
+    Email(Option<String>),
+    /// `[img]url[/img]`. The content is not parsed.
+    Image,
+    /// `[youtube]url[/youtube]`. The content is not parsed.
+    YouTube,
+    /// `[quote]` or `[quote=source]`.
+    Quote(Option<String>),
+    /// `[code]` or `[code=language]`. The content is not parsed.
+    Code(Option<String>),
+    /// `[hr]`, which has no content or closing tag.
+    HorizontalRule,
+}
+
+impl Tag {
+    /// Creates a tag from its name and argument, as in `[name=arg]`.
+    /// Returns [None] if the name is not a known tag.
+    pub fn from_parts(name: &str, arg: Option<&str>) -> Option<Self> {
+        let arg = arg.map(|a| a.trim_matches(|c| c == '"' || c == '\'').to_owned());
+        Some(match name.to_ascii_lowercase().as_str() {
+            "b" => Tag::Bold,
+            "i" => Tag::Italic,
+            "u" => Tag::Underline,
+            "s" => Tag::Strikethrough,
+            "smcaps" => Tag::SmallCaps,
+            "sup" => Tag::Superscript,
+            "sub" => Tag::Subscript,
+            "spoiler" => Tag::Spoiler,
+            "center" => Tag::Center,
+            "left" => Tag::Left,
+            "right" => Tag::Right,
+            "justify" => Tag::Justify,
+            "indent" => Tag::Indent(arg.and_then(|a| a.trim().parse().ok()).unwrap_or(1)),
+            "size" => Tag::Size(arg?),
+            "color" => Tag::Color(arg?),
+            "url" => Tag::Url(arg),
+            "email" => Tag::Email(arg),
+            "img" => Tag::Image,
+            "youtube" => Tag::YouTube,
+            "quote" => Tag::Quote(arg),
+            "code" => Tag::Code(arg),
+            "hr" => Tag::HorizontalRule,
+            _ => return None,
+        })
+    }
+
+    /// The name of the tag, as written in BBCode.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tag::Bold => "b",
+            Tag::Italic => "i",
+            Tag::Underline => "u",
+            Tag::Strikethrough => "s",
+            Tag::SmallCaps => "smcaps",
+            Tag::Superscript => "sup",
+            Tag::Subscript => "sub",
+            Tag::Spoiler => "spoiler",
+            Tag::Center => "center",
+            Tag::Left => "left",
+            Tag::Right => "right",
+            Tag::Justify => "justify",
+            Tag::Indent(_) => "indent",
+            Tag::Size(_) => "size",
+            Tag::Color(_) => "color",
+            Tag::Url(_) => "url",
+            Tag::Email(_) => "email",
+            Tag::Image => "img",
+            Tag::YouTube => "youtube",
+            Tag::Quote(_) => "quote",
+            Tag::Code(_) => "code",
+            Tag::HorizontalRule => "hr",
+        }
+    }
+
+    /// The argument of the tag, as written after `=` in BBCode.
+    pub fn arg(&self) -> Option<String> {
+        match self {
+            Tag::Indent(n) => Some(n.to_string()),
+            Tag::Size(a) | Tag::Color(a) => Some(a.clone()),
+            Tag::Url(a) | Tag::Email(a) | Tag::Quote(a) | Tag::Code(a) => a.clone(),
+            _ => None,
+        }
+    }
+
+    /// Whether the tag stands alone, without content or a closing tag.
+    pub fn is_void(&self) -> bool {
+        matches!(self, Tag::HorizontalRule)
+    }
+
+    /// Whether the content of the tag is kept as text instead of being parsed.
+    pub fn is_raw(&self) -> bool {
+        matches!(self, Tag::Image | Tag::YouTube | Tag::Code(_))
+    }
+
+    /// Whether the tag lays out its content as a block, rather than inline with the text.
+    pub fn is_block(&self) -> bool {
+        matches!(self, Tag::Center | Tag::Left | Tag::Right | Tag::Justify | Tag::Indent(_)
+            | Tag::Quote(_) | Tag::Code(_) | Tag::HorizontalRule | Tag::YouTube)
+    }
+}
+
+/// A tag and everything inside it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Element {
+    /// The tag.
+    pub tag: Tag,
+    /// The content of the tag. Raw tags contain a single text node, void tags nothing.
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    /// The text inside the element, without any markup.
+    pub fn text(&self) -> String {
+        plain_text(&self.children)
+    }
+}
+
+/// A node of a parsed BBCode document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    /// Plain text, including line breaks.
+    Text(String),
+    /// A tag and its content.
+    Element(Element),
+}
+
+impl Node {
+    /// Creates a text node.
+    pub fn text(text: impl Into<String>) -> Self {
+        Node::Text(text.into())
+    }
+
+    /// Creates an element node.
+    pub fn element(tag: Tag, children: Vec<Node>) -> Self {
+        Node::Element(Element { tag, children })
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Text(t) => f.write_str(t),
+            Node::Element(e) => {
+                match e.tag.arg() {
+                    Some(arg) => write!(f, "[{}={}]", e.tag.name(), escape_arg(&e.tag, &arg))?,
+                    None => write!(f, "[{}]", e.tag.name())?,
+                }
+                if e.tag.is_void() {
+                    return Ok(());
+                }
+                for child in &e.children {
+                    write!(f, "{}", child)?;
+                }
+                write!(f, "[/{}]", e.tag.name())
+            }
+        }
+    }
+}
+
+/// Writes the nodes back out as BBCode.
+pub fn to_bbcode(nodes: &[Node]) -> String {
+    nodes.iter().map(Node::to_string).collect()
+}
+
+/// Makes a tag argument safe to write out, since a tag ends at the first bracket or line break
+/// in it. URLs have brackets percent-encoded; other arguments get parentheses instead.
+fn escape_arg(tag: &Tag, arg: &str) -> String {
+    let is_url = matches!(tag, Tag::Url(_) | Tag::Email(_));
+    let mut out = String::with_capacity(arg.len());
+    for c in arg.chars() {
+        match c {
+            '[' if is_url => out.push_str("%5B"),
+            ']' if is_url => out.push_str("%5D"),
+            '[' => out.push('('),
+            ']' => out.push(')'),
+            '\n' | '\r' if is_url => {}
+            '\n' | '\r' => out.push(' '),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Inserted after the `[` of text which would be read as a tag. It is invisible, but keeps
+/// the text from being parsed as markup.
+const TAG_BREAK: char = '\u{200B}';
+
+/// Makes the text of a tree built from another format safe to write out as BBCode, so that
+/// text like `array[b]` stays text instead of becoming a tag. Adjacent text nodes are merged
+/// first, since a tag could be split across them.
+pub(crate) fn neutralize(nodes: &mut Vec<Node>) {
+    let mut merged: Vec<Node> = Vec::with_capacity(nodes.len());
+    for node in nodes.drain(..) {
+        match (merged.last_mut(), node) {
+            (Some(Node::Text(previous)), Node::Text(text)) => previous.push_str(&text),
+            (_, node) => merged.push(node),
+        }
+    }
+    *nodes = merged;
+
+    for node in nodes.iter_mut() {
+        match node {
+            Node::Text(text) => *text = break_tags(text, |rest| parse_tag(rest).is_some()),
+            // Raw content is only parsed for the closing tag.
+            Node::Element(e) if e.tag.is_raw() => {
+                let close = format!("[/{}]", e.tag.name());
+                for child in &mut e.children {
+                    if let Node::Text(text) = child {
+                        *text = break_tags(text, |rest| rest.get(..close.len()).is_some_and(|t| t.eq_ignore_ascii_case(&close)));
+                    }
+                }
+            }
+            Node::Element(e) => neutralize(&mut e.children),
+        }
+    }
+}
+
+/// Inserts a [TAG_BREAK] after every `[` starting text for which `is_tag` holds.
+fn break_tags(text: &str, is_tag: impl Fn(&str) -> bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('[') {
+        out.push_str(&rest[..=start]);
+        if is_tag(&rest[start..]) {
+            out.push(TAG_BREAK);
+        }
+        rest = &rest[start + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The text of the nodes, without any markup.
+pub fn plain_text(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(t) => out.push_str(t),
+            Node::Element(e) => out.push_str(&e.text()),
+        }
+    }
+    out
+}
+
+/// Parses BBCode into a tree of nodes.
+pub fn parse(input: &str) -> Vec<Node> {
+    // The open elements, innermost last. The bottom entry collects the top-level nodes.
+    let mut stack: Vec<(Option<Tag>, Vec<Node>)> = vec![(None, Vec::new())];
+    // Raw tags with no closing tag in the rest of the input, which need not be searched again.
+    let mut unclosed: Vec<&'static str> = Vec::new();
+    let mut rest = input;
+
+    while let Some(start) = rest.find('[') {
+        push_text(&mut stack, &rest[..start]);
+        rest = &rest[start..];
+
+        let token = match parse_tag(rest) {
+            Some(token) => token,
+            None => {
+                push_text(&mut stack, "[");
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        match token {
+            Token::Open(tag, len) if tag.is_void() => {
+                push_node(&mut stack, Node::element(tag, Vec::new()));
+                rest = &rest[len..];
+            }
+            Token::Open(tag, len) if tag.is_raw() => {
+                let close = format!("[/{}]", tag.name());
+                let end = if unclosed.contains(&tag.name()) {
+                    None
+                } else {
+                    find_ignore_case(&rest[len..], &close)
+                };
+                match end {
+                    Some(end) => {
+                        let content = &rest[len..len + end];
+                        let children = if content.is_empty() { Vec::new() } else { vec![Node::text(content)] };
+                        push_node(&mut stack, Node::element(tag, children));
+                        rest = &rest[len + end + close.len()..];
+                    }
+                    None => {
+                        unclosed.push(tag.name());
+                        push_text(&mut stack, &rest[..len]);
+                        rest = &rest[len..];
+                    }
+                }
+            }
+            Token::Open(tag, len) => {
+                stack.push((Some(tag), Vec::new()));
+                rest = &rest[len..];
+            }
+            Token::Close(name, len) => {
+                let open = stack.iter().rposition(|(tag, _)| tag.as_ref().is_some_and(|t| t.name() == name));
+                match open {
+                    Some(depth) => {
+                        while stack.len() > depth {
+                            close_top(&mut stack);
+                        }
+                    }
+                    None => push_text(&mut stack, &rest[..len]),
+                }
+                rest = &rest[len..];
+            }
+        }
+    }
+    push_text(&mut stack, rest);
+
+    while stack.len() > 1 {
+        close_top(&mut stack);
+    }
+    stack.pop().map(|(_, nodes)| nodes).unwrap_or_default()
+}
+
+/// The names of every known tag.
+const TAG_NAMES: &[&str] = &[
+    "b", "i", "u", "s", "smcaps", "sup", "sub", "spoiler", "center", "left", "right", "justify",
+    "indent", "size", "color", "url", "email", "img", "youtube", "quote", "code", "hr",
+];
+
+enum Token {
+    /// An opening tag and the length of its markup.
+    Open(Tag, usize),
+    /// The name of a closing tag and the length of its markup.
+    Close(&'static str, usize),
+}
+
+/// Parses the tag at the start of `s`, which starts with `[`.
+fn parse_tag(s: &str) -> Option<Token> {
+    let end = s[1..].find([']', '[', '\n'])? + 1;
+    if !s[end..].starts_with(']') {
+        return None;
+    }
+    let inner = &s[1..end];
+    let len = end + 1;
+
+    if let Some(name) = inner.strip_prefix('/') {
+        let name = name.trim();
+        let name = TAG_NAMES.iter().find(|n| n.eq_ignore_ascii_case(name))?;
+        return Some(Token::Close(name, len));
+    }
+
+    let (name, arg) = match inner.find('=') {
+        Some(i) => (&inner[..i], Some(&inner[i + 1..])),
+        None => (inner, None),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Tag::from_parts(name, arg).map(|tag| Token::Open(tag, len))
+}
+
+/// Finds an ASCII needle in the haystack, ignoring ASCII case, without allocating.
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    let needle = needle.as_bytes();
+    haystack.as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+fn push_text(stack: &mut [(Option<Tag>, Vec<Node>)], text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    let nodes = &mut stack.last_mut().expect("the stack always has a root").1;
+    match nodes.last_mut() {
+        Some(Node::Text(t)) => t.push_str(text),
+        _ => nodes.push(Node::text(text)),
+    }
+}
+
+fn push_node(stack: &mut [(Option<Tag>, Vec<Node>)], node: Node) {
+    stack.last_mut().expect("the stack always has a root").1.push(node);
+}
+
+fn close_top(stack: &mut Vec<(Option<Tag>, Vec<Node>)>) {
+    if let Some((Some(tag), children)) = stack.pop() {
+        push_node(stack, Node::element(tag, children));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn el(tag: Tag, children: Vec<Node>) -> Node {
+        Node::element(tag, children)
+    }
+
+    #[test]
+    fn parses_nested_tags() {
+        let nodes = parse("[center][b]Bold [I]and italic[/i][/B]\n[size=2em]Big[/size][/center]");
+        assert_eq!(nodes, vec![el(Tag::Center, vec![
+            el(Tag::Bold, vec![Node::text("Bold "), el(Tag::Italic, vec![Node::text("and italic")])]),
+            Node::text("\n"),
+            el(Tag::Size("2em".into()), vec![Node::text("Big")]),
+        ])]);
+    }
+
+    #[test]
+    fn parses_site_specific_tags() {
+        let nodes = parse("[spoiler]twist[/spoiler][hr][youtube]https://youtu.be/x[b][/youtube][url=\"https://x.y\"]link[/url]");
+        assert_eq!(nodes, vec![
+            el(Tag::Spoiler, vec![Node::text("twist")]),
+            el(Tag::HorizontalRule, vec![]),
+            el(Tag::YouTube, vec![Node::text("https://youtu.be/x[b]")]),
+            el(Tag::Url(Some("https://x.y".into())), vec![Node::text("link")]),
+        ]);
+    }
+
+    #[test]
+    fn keeps_malformed_markup_as_text() {
+        assert_eq!(parse("[unknown]a[/unknown] [b a [/i] [size]x"), vec![Node::text("[unknown]a[/unknown] [b a [/i] [size]x")]);
+        assert_eq!(parse("[img]never closed"), vec![Node::text("[img]never closed")]);
+    }
+
+    #[test]
+    fn finds_raw_closing_tags_in_any_case() {
+        assert_eq!(parse("[CODE]a [b] b[/Code]"), vec![el(Tag::Code(None), vec![Node::text("a [b] b")])]);
+
+        // Each unclosed tag must not search the rest of the input again.
+        let input = "[code]".repeat(50_000);
+        assert_eq!(parse(&input), vec![Node::text(input.clone())]);
+    }
+
+    #[test]
+    fn closes_unbalanced_tags() {
+        assert_eq!(parse("[b][i]x[/b]y"), vec![
+            el(Tag::Bold, vec![el(Tag::Italic, vec![Node::text("x")])]),
+            Node::text("y"),
+        ]);
+        assert_eq!(parse("[quote=Twilight]x"), vec![el(Tag::Quote(Some("Twilight".into())), vec![Node::text("x")])]);
+    }
+
+    #[test]
+    fn round_trips() {
+        let input = "[quote=Rarity][color=#ff00ff]Darling[/color][/quote]\n[indent=2][smcaps]Ch. 1[/smcaps][/indent][hr][code]let [b] = 1;[/code]";
+        assert_eq!(to_bbcode(&parse(input)), input);
+        assert_eq!(plain_text(&parse("[b]a[/b][i]b[/i]")), "ab");
+    }
+
+    #[test]
+    fn escapes_brackets_in_arguments() {
+        let nodes = vec![
+            el(Tag::Url(Some("https://x.y/a[1]".into())), vec![Node::text("link")]),
+            el(Tag::Quote(Some("Rarity [ed.]".into())), vec![Node::text("x")]),
+        ];
+        let bbcode = to_bbcode(&nodes);
+        assert_eq!(bbcode, "[url=https://x.y/a%5B1%5D]link[/url][quote=Rarity (ed.)]x[/quote]");
+        assert_eq!(parse(&bbcode).len(), 2);
+    }
+}
@@ -0,0 +1,420 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains helpers to export a user's bookshelves and import them into another account, such
+//! as a new one after account recovery.
+//!
+//! A [BookshelfExport] can be saved as JSON, or as CSV with one row per story:
+//!
+//! ```text
+//! bookshelf,privacy,description,story_id
+//! Favourites,public,"The best of the best, ""truly"".",1
+//! Favourites,public,"The best of the best, ""truly"".",3
+//! Read Later,private,,
+//! ```
+//!
+//! Bookshelves with no stories get a single row without a story id, so they survive the trip.
+//!
+//! Requests are sent in batches of [batch_size][BookshelfTransfer::batch_size]. Rate-limited
+//! requests are retried by the client's [RetryPolicy][crate::retry::RetryPolicy]; if a batch
+//! still ends rate limited, the next batch waits until the limit resets.
+
+use crate::archive::parse_id;
+use crate::client::Client;
+use crate::model::BookshelfChanges;
+use crate::response::Error;
+use crate::util::write_csv_record;
+use futures::Future;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+/// The default number of requests a [BookshelfTransfer] sends at once.
+pub const DEFAULT_BATCH_SIZE: usize = 4;
+
+/// The errors which can occur while exporting or importing bookshelves.
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum BookshelfError {
+    /// A request to FimFic failed.
+    #[error("request failed: {0}")]
+    Request(#[from] Error),
+    /// An export could not be read from or written to JSON.
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    /// An export could not be read from CSV.
+    #[error("invalid CSV on line {line}: {reason}")]
+    Csv {
+        /// The line the bad record starts on, starting from 1.
+        line: usize,
+        /// What is wrong with it.
+        reason: String,
+    },
+}
+
+/// A bookshelf and the stories on it, as exported.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedBookshelf {
+    /// The name of the bookshelf.
+    pub name: String,
+    /// The description, in plain text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Who can see the bookshelf: `public`, `unlisted`, or `private`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privacy: Option<String>,
+    /// The ids of the stories on the bookshelf.
+    #[serde(default)]
+    pub story_ids: Vec<u64>,
+}
+
+/// Every bookshelf of a user, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookshelfExport {
+    /// The bookshelves.
+    pub bookshelves: Vec<ExportedBookshelf>,
+}
+
+const CSV_HEADER: [&str; 4] = ["bookshelf", "privacy", "description", "story_id"];
+
+impl BookshelfExport {
+    /// Writes the export as pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String, BookshelfError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Reads an export written by [to_json][Self::to_json].
+    pub fn from_json(json: &str) -> Result<Self, BookshelfError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Writes the export as CSV, with one row per story.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        write_csv_record(&mut out, &CSV_HEADER);
+        for shelf in &self.bookshelves {
+            let privacy = shelf.privacy.as_deref().unwrap_or_default();
+            let description = shelf.description.as_deref().unwrap_or_default();
+            if shelf.story_ids.is_empty() {
+                write_csv_record(&mut out, &[&shelf.name, privacy, description, ""]);
+            }
+            for id in &shelf.story_ids {
+                write_csv_record(&mut out, &[&shelf.name, privacy, description, &id.to_string()]);
+            }
+        }
+        out
+    }
+
+    /// Reads an export written by [to_csv][Self::to_csv]. The columns may be in any order, and
+    /// only `bookshelf` and `story_id` are required. Rows of the same bookshelf are merged.
+    pub fn from_csv(csv: &str) -> Result<Self, BookshelfError> {
+        let mut records = parse_csv(csv)?.into_iter();
+        let (_, header) = records.next().ok_or(BookshelfError::Csv { line: 1, reason: "missing header".into() })?;
+        let column = |name: &str| header.iter().position(|h| h.trim().eq_ignore_ascii_case(name));
+        let missing = |name: &str| BookshelfError::Csv { line: 1, reason: format!("missing column {:?}", name) };
+        let name_col = column("bookshelf").ok_or_else(|| missing("bookshelf"))?;
+        let id_col = column("story_id").ok_or_else(|| missing("story_id"))?;
+        let privacy_col = column("privacy");
+        let description_col = column("description");
+
+        let mut export = BookshelfExport::default();
+        for (line, record) in records {
+            let field = |col: Option<usize>| col.and_then(|c| record.get(c)).map(String::as_str).filter(|f| !f.is_empty());
+            let name = field(Some(name_col))
+                .ok_or(BookshelfError::Csv { line, reason: "missing bookshelf name".into() })?;
+
+            let index = match export.bookshelves.iter().position(|s| s.name == name) {
+                Some(index) => index,
+                None => {
+                    export.bookshelves.push(ExportedBookshelf { name: name.to_owned(), ..Default::default() });
+                    export.bookshelves.len() - 1
+                }
+            };
+            let shelf = &mut export.bookshelves[index];
+            if shelf.privacy.is_none() {
+                shelf.privacy = field(privacy_col).map(str::to_owned);
+            }
+            if shelf.description.is_none() {
+                shelf.description = field(description_col).map(str::to_owned);
+            }
+            if let Some(id) = field(Some(id_col)) {
+                let id = id.trim().parse()
+                    .map_err(|_| BookshelfError::Csv { line, reason: format!("story id {:?} is not a number", id) })?;
+                shelf.story_ids.push(id);
+            }
+        }
+        Ok(export)
+    }
+}
+
+/// A story which could not be added to a bookshelf during an import.
+#[derive(Debug)]
+pub struct ImportFailure {
+    /// The name of the bookshelf.
+    pub bookshelf: String,
+    /// The id of the story.
+    pub story_id: u64,
+    /// Why it could not be added, e.g. because the story was deleted.
+    pub error: Error,
+}
+
+/// What happened while importing bookshelves.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    /// The number of bookshelves which did not exist yet and were created.
+    pub bookshelves_created: usize,
+    /// The number of stories added to bookshelves.
+    pub items_added: usize,
+    /// The number of stories which were already on their bookshelf.
+    pub items_skipped: usize,
+    /// The stories which could not be added.
+    pub failures: Vec<ImportFailure>,
+}
+
+/// Exports and imports bookshelves, batching requests.
+#[derive(Debug, Clone)]
+pub struct BookshelfTransfer {
+    batch_size: usize,
+    batch_delay: Duration,
+}
+
+impl Default for BookshelfTransfer {
+    fn default() -> Self {
+        BookshelfTransfer {
+            batch_size: DEFAULT_BATCH_SIZE,
+            batch_delay: Duration::from_secs(0),
+        }
+    }
+}
+
+impl BookshelfTransfer {
+    /// Creates a transfer which sends [DEFAULT_BATCH_SIZE] requests at once.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of requests sent at once. The client's
+    /// [max_concurrent_requests][crate::client::ClientBuilder::max_concurrent_requests] still
+    /// applies.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Sets how long to wait between batches, to stay clear of the rate limit.
+    pub fn batch_delay(mut self, batch_delay: Duration) -> Self {
+        self.batch_delay = batch_delay;
+        self
+    }
+
+    /// Exports every bookshelf of the user with the given id, with the stories on it. Private
+    /// bookshelves are only included if the client is authorized as that user.
+    pub async fn export(&self, client: &Client, user_id: u64) -> Result<BookshelfExport, BookshelfError> {
+        let mut shelves = client.user_bookshelves(user_id).await?;
+        shelves.sort_by_key(|s| s.attributes.order.unwrap_or(u64::MAX));
+        let ids = shelves.iter().map(|s| parse_id(&s.id)).collect::<Result<Vec<_>, _>>()?;
+        let items = self.batched(&ids, |id| client.bookshelf_items(id)).await;
+
+        let mut export = BookshelfExport::default();
+        for (shelf, items) in shelves.into_iter().zip(items) {
+            let story_ids = items?.iter()
+                .filter_map(|item| item.related_id("story").map(parse_id))
+                .collect::<Result<_, _>>()?;
+            export.bookshelves.push(ExportedBookshelf {
+                name: shelf.attributes.name,
+                description: shelf.attributes.description,
+                privacy: shelf.attributes.privacy,
+                story_ids,
+            });
+        }
+        Ok(export)
+    }
+
+    /// Imports bookshelves into the account of the user with the given id, which the client
+    /// must be authorized as. Bookshelves are matched by name and created if they do not exist,
+    /// and stories already on them are skipped, so an interrupted import can simply be run
+    /// again. Stories which cannot be added are reported rather than stopping the import.
+    pub async fn import(&self, client: &Client, user_id: u64, export: &BookshelfExport) -> Result<ImportSummary, BookshelfError> {
+        let existing = client.user_bookshelves(user_id).await?;
+        let mut summary = ImportSummary::default();
+
+        for shelf in &export.bookshelves {
+            let found = existing.iter().find(|s| s.attributes.name == shelf.name);
+            let (id, present) = match found {
+                Some(found) => {
+                    let id = parse_id(&found.id)?;
+                    let present = client.bookshelf_items(id).await?.iter()
+                        .filter_map(|item| item.related_id("story").map(parse_id))
+                        .collect::<Result<BTreeSet<u64>, _>>()?;
+                    (id, present)
+                }
+                None => {
+                    let created = client.create_bookshelf(&BookshelfChanges {
+                        name: Some(shelf.name.clone()),
+                        description: shelf.description.clone(),
+                        privacy: shelf.privacy.clone(),
+                    }).await?;
+                    summary.bookshelves_created += 1;
+                    (parse_id(&created.id)?, BTreeSet::new())
+                }
+            };
+
+            let mut seen = present;
+            let missing: Vec<u64> = shelf.story_ids.iter().copied().filter(|id| seen.insert(*id)).collect();
+            summary.items_skipped += shelf.story_ids.len() - missing.len();
+
+            let results = self.batched(&missing, |story_id| client.add_bookshelf_item(id, story_id)).await;
+            for (story_id, result) in missing.into_iter().zip(results) {
+                match result {
+                    Ok(_) => summary.items_added += 1,
+                    Err(error) => summary.failures.push(ImportFailure { bookshelf: shelf.name.clone(), story_id, error }),
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Calls `f` for every item, [batch_size][Self::batch_size] at a time, returning the
+    /// results in the same order as the items.
+    async fn batched<T, F, Fut>(&self, items: &[u64], f: F) -> Vec<Result<T, Error>>
+        where F: Fn(u64) -> Fut,
+              Fut: Future<Output = Result<T, Error>> {
+        let mut results = Vec::with_capacity(items.len());
+        let mut wait = Duration::from_secs(0);
+        for batch in items.chunks(self.batch_size) {
+            if wait > Duration::from_secs(0) {
+                tokio::time::delay_for(wait).await;
+            }
+            let batch = futures::future::join_all(batch.iter().map(|&item| f(item))).await;
+            let rate_limited = batch.iter().filter_map(|r| r.as_ref().err()).filter_map(Error::retry_after).max();
+            wait = rate_limited.map_or(self.batch_delay, |w| w.max(self.batch_delay));
+            results.extend(batch);
+        }
+        results
+    }
+}
+
+/// Splits CSV into records, each with the line it starts on. Blank lines are skipped.
+fn parse_csv(csv: &str) -> Result<Vec<(usize, Vec<String>)>, BookshelfError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if field.is_empty() => in_quotes = true,
+            ',' if !in_quotes => record.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes && chars.peek() == Some(&'\n') => {}
+            '\n' if !in_quotes => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|f| !f.is_empty()) {
+                    records.push((record_line, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                record_line = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if in_quotes {
+        return Err(BookshelfError::Csv { line: record_line, reason: "unterminated quoted field".into() });
+    }
+    record.push(field);
+    if record.iter().any(|f| !f.is_empty()) {
+        records.push((record_line, record));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fixtures, MockClient};
+
+    fn export() -> BookshelfExport {
+        BookshelfExport {
+            bookshelves: vec![
+                ExportedBookshelf {
+                    name: "Favourites".into(),
+                    description: Some("The best of the best, \"truly\".".into()),
+                    privacy: Some("public".into()),
+                    story_ids: vec![1, 3],
+                },
+                ExportedBookshelf { name: "Read Later".into(), privacy: Some("private".into()), ..Default::default() },
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn exports_bookshelves() {
+        let mock = MockClient::new();
+        mock.on_get("/bookshelves", fixtures::BOOKSHELVES)
+            .on_get("/bookshelves/21/items", fixtures::BOOKSHELF_ITEMS)
+            .on_get("/bookshelves/22/items", r#"{"data":[]}"#);
+
+        let exported = BookshelfTransfer::new().export(&mock.client(), 2).await.unwrap();
+        assert_eq!(exported, export());
+        assert_eq!(mock.requests()[0].path, "/bookshelves?filter[user]=2");
+    }
+
+    #[test]
+    fn round_trips_through_csv_and_json() {
+        let csv = export().to_csv();
+        assert_eq!(csv, "bookshelf,privacy,description,story_id\n\
+            Favourites,public,\"The best of the best, \"\"truly\"\".\",1\n\
+            Favourites,public,\"The best of the best, \"\"truly\"\".\",3\n\
+            Read Later,private,,\n");
+        assert_eq!(BookshelfExport::from_csv(&csv).unwrap(), export());
+        assert_eq!(BookshelfExport::from_json(&export().to_json().unwrap()).unwrap(), export());
+    }
+
+    #[test]
+    fn reads_loose_csv() {
+        let csv = "story_id,Bookshelf\r\n5,\"Multi\nline\"\r\n\r\n6,\"Multi\nline\"\n,Empty";
+        let export = BookshelfExport::from_csv(csv).unwrap();
+        assert_eq!(export.bookshelves.len(), 2);
+        assert_eq!(export.bookshelves[0].name, "Multi\nline");
+        assert_eq!(export.bookshelves[0].story_ids, vec![5, 6]);
+
+        let err = BookshelfExport::from_csv("bookshelf,story_id\nA,1\n\"B\",x").unwrap_err();
+        assert!(matches!(err, BookshelfError::Csv { line: 3, .. }), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn imports_into_another_account() {
+        let mock = MockClient::new();
+        let shelf = |id: u64, name: &str| format!(r#"{{"id":"{}","type":"bookshelf","attributes":{{"name":"{}"}}}}"#, id, name);
+        let item = |story: u64| format!(r#"{{"id":"9{}","type":"bookshelf_item","attributes":{{}},"relationships":{{"story":{{"data":{{"type":"story","id":"{}"}}}}}}}}"#, story, story);
+        mock.on_get("/bookshelves", format!(r#"{{"data":[{}]}}"#, shelf(41, "Favourites")))
+            .on_get("/bookshelves/41/items", format!(r#"{{"data":[{}]}}"#, item(1)))
+            .on("POST", "/bookshelves", 201, format!(r#"{{"data":{}}}"#, shelf(42, "Read Later")))
+            .on("POST", "/bookshelves/41/items", 404, fixtures::NOT_FOUND);
+
+        let mut export = export();
+        export.bookshelves[1].story_ids = vec![4, 4];
+        mock.on("POST", "/bookshelves/42/items", 201, format!(r#"{{"data":{}}}"#, item(4)));
+
+        let summary = BookshelfTransfer::new().batch_size(1).import(&mock.client(), 5, &export).await.unwrap();
+        assert_eq!((summary.bookshelves_created, summary.items_added, summary.items_skipped), (1, 1, 2));
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!((summary.failures[0].bookshelf.as_str(), summary.failures[0].story_id), ("Favourites", 3));
+
+        let created: serde_json::Value = serde_json::from_str(mock.requests().iter()
+            .find(|r| r.method == "POST" && r.path == "/bookshelves").unwrap().body.as_deref().unwrap()).unwrap();
+        assert_eq!(created["data"]["attributes"], serde_json::json!({"name": "Read Later", "privacy": "private"}));
+    }
+}
@@ -1,6 +1,17 @@
 //! This module contains an implementation of an HTTP client for communicating with the FimFic servers
 
-use crate::response::{Error, extract_api_response};
+use crate::response::{Error, extract_api_response, extract_bytes};
+use crate::response::error::Trace;
+use crate::transport::Transport;
+use crate::model::{Bookshelf, BookshelfChanges, BookshelfItem, Chapter, ChapterChanges, ChapterRead, Document, Relationship, RelationshipData, ResourceId, Story, StoryChanges, User};
+use std::collections::{BTreeMap, HashSet};
+use crate::retry::{ExponentialBackoff, RateLimit, RetryDecision, RetryPolicy};
+use reqwest::header::AUTHORIZATION;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use crate::options::RequestOptions;
+use futures::{Future, FutureExt};
+use std::time::{Duration, Instant};
 
 macro_rules! endpoint {
     () => {"https://www.fimfiction.net/api/v2"};
@@ -10,12 +21,19 @@ macro_rules! endpoint {
 /// The URL for the fimfiction API
 pub const BASE_URL: &str = endpoint!();
 
+/// The default number of requests a [Client] will have in flight at once.
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 8;
+
 
 /// Client for making requests through FimFic API. This type will only support simple client credentials.
 #[derive(Clone, Debug)]
 pub struct Client {
     bearer_token: String,
     client: reqwest::Client,
+    transport: Arc<dyn Transport>,
+    retry_policy: Arc<dyn RetryPolicy>,
+    limiter: Arc<Semaphore>,
+    options: RequestOptions,
 }
 
 impl Client {
@@ -26,43 +44,612 @@ impl Client {
 
     /// Creates a client with the given [HTTP Client][reqwest::Client].
     pub async fn with_client(client_id: impl AsRef<str>, client_secret: impl AsRef<str>, http: reqwest::Client) -> Result<Self, Error> {
-        let form = [
-            ("client_id", client_id.as_ref()),
-            ("client_secret", client_secret.as_ref()),
-            ("grant_type", "client_credentials")
-        ];
-
-        let res = http.post(endpoint!("/token"))
-            .form(&form)
-            .send()
-            .await?;
-
-        let value: serde_json::Value = extract_api_response(res).await?;
-        Ok(Client {
-            bearer_token: format!("Bearer {}", value.get("access_token").unwrap().as_str().unwrap()),
-            client: http,
-        })
+        ClientBuilder::new()
+            .http_client(http)
+            .build_with_credentials(client_id, client_secret)
+            .await
     }
 
     /// Creates a client from the given bearer token. This does not verify that this is a valid token,
     /// so if it's not valid, you will be receiving a lot of [APIErrors][crate::response::error::APIError]
     pub fn from_token(tok: impl Into<String>) -> Self {
-        Client {
-            bearer_token: tok.into(),
-            client: reqwest::Client::default(),
-        }
+        ClientBuilder::new().finish(reqwest::Client::default(), tok.into())
+    }
+
+    /// Creates a [ClientBuilder] for configuring a client.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
     }
 
     /// Accessor for the bearer token. You can save one that is generated and reuse it in the future.
     pub fn bearer_token(&self) -> &str {
         &self.bearer_token
     }
+
+    /// Returns a copy of this client which applies the given [RequestOptions] to every request.
+    /// The copy shares its connections and concurrency limit with this client.
+    pub fn with_options(&self, options: RequestOptions) -> Client {
+        Client {
+            options,
+            ..self.clone()
+        }
+    }
+
+    /// The [RequestOptions] applied to every request made by this client.
+    pub fn options(&self) -> &RequestOptions {
+        &self.options
+    }
+
+    /// Fetches the story with the given id.
+    pub async fn story(&self, id: u64) -> Result<Story, Error> {
+        let doc: Document<Story> = self.get(&format!("/stories/{}", id)).await?;
+        Ok(doc.data)
+    }
+
+    /// Fetches the user with the given id.
+    pub async fn user(&self, id: u64) -> Result<User, Error> {
+        let doc: Document<User> = self.get(&format!("/users/{}", id)).await?;
+        Ok(doc.data)
+    }
+
+    /// Fetches the chapters of the story with the given id, in order, following pagination.
+    /// The chapters do not include their text; fetch each one with [chapter][Self::chapter]
+    /// for that.
+    pub async fn story_chapters(&self, story_id: u64) -> Result<Vec<Chapter>, Error> {
+        self.get_all(&format!("/stories/{}/chapters", story_id)).await
+    }
+
+    /// Fetches every story written by the user with the given id, following pagination.
+    pub async fn user_stories(&self, user_id: u64) -> Result<Vec<Story>, Error> {
+        self.get_all(&format!("/stories?filter[author]={}", user_id)).await
+    }
+
+    /// Fetches the chapter with the given id, including its text.
+    pub async fn chapter(&self, id: u64) -> Result<Chapter, Error> {
+        let doc: Document<Chapter> = self.get(&format!("/chapters/{}", id)).await?;
+        Ok(doc.data)
+    }
+
+    /// Creates a chapter at the end of the story with the given id.
+    pub async fn create_chapter(&self, story_id: u64, chapter: &ChapterChanges) -> Result<Chapter, Error> {
+        let path = format!("/stories/{}/chapters", story_id);
+        let doc: Document<Chapter> = self.send_resource(reqwest::Method::POST, &path, "chapter", None, chapter, BTreeMap::new()).await?;
+        Ok(doc.data)
+    }
+
+    /// Updates the chapter with the given id. Attributes which are not set are left alone.
+    pub async fn update_chapter(&self, id: u64, changes: &ChapterChanges) -> Result<Chapter, Error> {
+        let path = format!("/chapters/{}", id);
+        let doc: Document<Chapter> = self.send_resource(reqwest::Method::PATCH, &path, "chapter", Some(id), changes, BTreeMap::new()).await?;
+        Ok(doc.data)
+    }
+
+    /// Creates a story. New stories are not published until they are updated to be.
+    pub async fn create_story(&self, story: &StoryChanges) -> Result<Story, Error> {
+        let doc: Document<Story> = self.send_resource(reqwest::Method::POST, "/stories", "story", None, story, BTreeMap::new()).await?;
+        Ok(doc.data)
+    }
+
+    /// Updates the story with the given id. Attributes which are not set are left alone.
+    pub async fn update_story(&self, id: u64, changes: &StoryChanges) -> Result<Story, Error> {
+        let path = format!("/stories/{}", id);
+        let doc: Document<Story> = self.send_resource(reqwest::Method::PATCH, &path, "story", Some(id), changes, BTreeMap::new()).await?;
+        Ok(doc.data)
+    }
+
+    /// Replaces the tags of the story with the given id.
+    pub async fn set_story_tags(&self, id: u64, tag_ids: &[u64]) -> Result<Story, Error> {
+        let tags = tag_ids.iter()
+            .map(|tag| ResourceId { id: tag.to_string(), kind: "story_tag".into() })
+            .collect();
+        let mut relationships = BTreeMap::new();
+        relationships.insert("tags".to_owned(), Relationship { data: RelationshipData::Many(tags) });
+        let path = format!("/stories/{}", id);
+        let doc: Document<Story> = self.send_resource(reqwest::Method::PATCH, &path, "story", Some(id), &StoryChanges::default(), relationships).await?;
+        Ok(doc.data)
+    }
+
+    /// Uploads the cover art of the story with the given id, replacing any it had.
+    /// `media_type` is the type of the image, e.g. `image/png`.
+    pub async fn upload_cover(&self, id: u64, image: Vec<u8>, media_type: &str) -> Result<Story, Error> {
+        let req = self.client.post(&format!("{}/stories/{}/cover", BASE_URL, id))
+            .header(AUTHORIZATION, &self.bearer_token)
+            .header(reqwest::header::CONTENT_TYPE, media_type)
+            .body(image);
+        let doc: Document<Story> = self.send(req).await?;
+        Ok(doc.data)
+    }
+
+    /// Fetches every bookshelf of the user with the given id, following pagination. Private
+    /// bookshelves are only included for the user the client is authorized as.
+    pub async fn user_bookshelves(&self, user_id: u64) -> Result<Vec<Bookshelf>, Error> {
+        self.get_all(&format!("/bookshelves?filter[user]={}", user_id)).await
+    }
+
+    /// Fetches every item on the bookshelf with the given id, following pagination.
+    pub async fn bookshelf_items(&self, bookshelf_id: u64) -> Result<Vec<BookshelfItem>, Error> {
+        self.get_all(&format!("/bookshelves/{}/items", bookshelf_id)).await
+    }
+
+    /// Creates a bookshelf for the user the client is authorized as.
+    pub async fn create_bookshelf(&self, bookshelf: &BookshelfChanges) -> Result<Bookshelf, Error> {
+        let doc: Document<Bookshelf> = self.send_resource(reqwest::Method::POST, "/bookshelves", "bookshelf", None, bookshelf, BTreeMap::new()).await?;
+        Ok(doc.data)
+    }
+
+    /// Adds the story with the given id to a bookshelf.
+    pub async fn add_bookshelf_item(&self, bookshelf_id: u64, story_id: u64) -> Result<BookshelfItem, Error> {
+        let story = ResourceId { id: story_id.to_string(), kind: "story".into() };
+        let mut relationships = BTreeMap::new();
+        relationships.insert("story".to_owned(), Relationship { data: RelationshipData::One(Some(story)) });
+        let path = format!("/bookshelves/{}/items", bookshelf_id);
+        let attributes = serde_json::json!({});
+        let doc: Document<BookshelfItem> = self.send_resource(reqwest::Method::POST, &path, "bookshelf_item", None, &attributes, relationships).await?;
+        Ok(doc.data)
+    }
+
+    /// Fetches every chapter the user with the given id has read, following pagination. Only
+    /// available for the user the client is authorized as.
+    pub async fn chapter_reads(&self, user_id: u64) -> Result<Vec<ChapterRead>, Error> {
+        self.get_all(&format!("/chapter-reads?filter[user]={}", user_id)).await
+    }
+
+    /// Fetches several stories at once. Results are returned in the same order as the ids.
+    /// The number of simultaneous requests is bounded by
+    /// [max_concurrent_requests][ClientBuilder::max_concurrent_requests].
+    pub async fn batch_stories(&self, ids: impl IntoIterator<Item = u64>) -> Vec<Result<Story, Error>> {
+        futures::future::join_all(ids.into_iter().map(|id| self.story(id))).await
+    }
+
+    /// Sends an authenticated GET request for the given path, relative to [BASE_URL].
+    pub(crate) async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        let req = self.client.get(&format!("{}{}", BASE_URL, path))
+            .header(AUTHORIZATION, &self.bearer_token);
+        self.send(req).await
+    }
+
+    /// Sends an authenticated request whose body is a single resource with the given
+    /// attributes and relationships, as when creating or updating a resource.
+    pub(crate) async fn send_resource<T: serde::de::DeserializeOwned>(
+        &self, method: reqwest::Method, path: &str, kind: &str, id: Option<u64>, attributes: &impl serde::Serialize,
+        relationships: BTreeMap<String, Relationship>,
+    ) -> Result<T, Error> {
+        let mut data = serde_json::json!({ "type": kind, "attributes": attributes });
+        if let Some(id) = id {
+            data["id"] = id.to_string().into();
+        }
+        if !relationships.is_empty() {
+            data["relationships"] = serde_json::json!(relationships);
+        }
+        let req = self.client.request(method, &format!("{}{}", BASE_URL, path))
+            .header(AUTHORIZATION, &self.bearer_token)
+            .json(&serde_json::json!({ "data": data }));
+        self.send(req).await
+    }
+
+    /// Fetches every page of a collection, starting at the given path and following the
+    /// `next` links until the last page.
+    ///
+    /// The token is sent with every page, so only links back into the API are followed, and
+    /// each at most once.
+    pub(crate) async fn get_all<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<Vec<T>, Error> {
+        let mut url = format!("{}{}", BASE_URL, path);
+        let mut seen = HashSet::new();
+        let mut items = Vec::new();
+        loop {
+            seen.insert(url.clone());
+            let req = self.client.get(&url)
+                .header(AUTHORIZATION, &self.bearer_token);
+            let doc: Document<Vec<T>> = self.send(req).await?;
+            items.extend(doc.data);
+            let next = match doc.links.and_then(|l| l.next) {
+                Some(next) => next,
+                None => return Ok(items),
+            };
+            let in_api = next.strip_prefix(BASE_URL).is_some_and(|rest| rest.starts_with('/') || rest.starts_with('?'));
+            if !in_api || seen.contains(&next) {
+                return Err(Error::UnexpectedResponse {
+                    status: reqwest::StatusCode::OK,
+                    reason: format!("refusing to follow next link {:?}", next),
+                    trace: Trace::capture(),
+                });
+            }
+            url = next;
+        }
+    }
+
+    /// Sends a request and extracts the API response, retrying according to the [RetryPolicy]
+    /// and giving up early according to the [RequestOptions].
+    pub(crate) async fn send<T: serde::de::DeserializeOwned>(&self, req: reqwest::RequestBuilder) -> Result<T, Error> {
+        self.send_with(req, extract_api_response).await
+    }
+
+    /// Downloads a file which is not part of the API, such as a cover image, with the same
+    /// retries and limits as API requests.
+    pub(crate) async fn download(&self, url: &str) -> Result<Vec<u8>, Error> {
+        self.send_with(self.client.get(url), extract_bytes).await
+    }
+
+    async fn send_with<T, F, Fut>(&self, req: reqwest::RequestBuilder, extract: F) -> Result<T, Error>
+        where F: Fn(reqwest::Response) -> Fut,
+              Fut: Future<Output = Result<T, Error>> {
+        self.send_with_options(req, extract).await
+    }
+
+    async fn send_with_options<T, F, Fut>(&self, req: reqwest::RequestBuilder, extract: F) -> Result<T, Error>
+        where F: Fn(reqwest::Response) -> Fut,
+              Fut: Future<Output = Result<T, Error>> {
+        if self.options.is_unlimited() {
+            return self.send_with_retries(req, extract).await;
+        }
+
+        let deadline = self.options.deadline_from(Instant::now());
+        let cancellation = self.options.cancellation_token();
+        let expired = async {
+            match deadline {
+                Some(d) => tokio::time::delay_until(d.into()).await,
+                None => futures::future::pending().await,
+            }
+        };
+        let cancelled = async {
+            match cancellation {
+                Some(c) => c.cancelled().await,
+                None => futures::future::pending().await,
+            }
+        };
+
+        // Dropping the losing future aborts the underlying HTTP request.
+        futures::select_biased! {
+            _ = cancelled.fuse() => Err(Error::Cancelled(Trace::capture())),
+            _ = expired.fuse() => Err(Error::DeadlineExceeded(Trace::capture())),
+            res = self.send_with_retries(req, extract).fuse() => res,
+        }
+    }
+
+    async fn send_with_retries<T, F, Fut>(&self, req: reqwest::RequestBuilder, extract: F) -> Result<T, Error>
+        where F: Fn(reqwest::Response) -> Fut,
+              Fut: Future<Output = Result<T, Error>> {
+        let mut req = req.build()?;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let retry = req.try_clone();
+            let method = req.method().clone();
+            let permit = self.limiter.acquire().await;
+            let (res, rate_limit) = match self.transport.execute(req).await {
+                Ok(res) => {
+                    let rate_limit = RateLimit::from_headers(res.headers());
+                    (extract(res).await, rate_limit)
+                }
+                Err(e) => (Err(e), None),
+            };
+            drop(permit);
+
+            let err = match res {
+                Ok(o) => return Ok(o),
+                Err(e) => e,
+            };
+            match (retry, self.retry_policy.decide(&method, attempt, &err, rate_limit.as_ref())) {
+                (Some(next), RetryDecision::RetryAfter(delay)) => {
+                    tokio::time::delay_for(delay).await;
+                    req = next;
+                }
+                _ => return Err(err),
+            }
+        }
+    }
+}
+
+/// Builder for a [Client], for when the defaults are not enough.
+#[derive(Debug, Default)]
+pub struct ClientBuilder {
+    http: Option<reqwest::Client>,
+    transport: Option<Arc<dyn Transport>>,
+    retry_policy: Option<Arc<dyn RetryPolicy>>,
+    max_concurrent_requests: Option<usize>,
+    pool_idle_timeout: Option<Option<Duration>>,
+    pool_max_idle_per_host: Option<usize>,
+    tcp_keepalive: Option<Duration>,
+}
+
+impl ClientBuilder {
+    /// Creates a builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses the given [HTTP Client][reqwest::Client] to send requests.
+    /// This can not be combined with the connection options on this builder, which are only
+    /// used to create the client; configure the given client instead.
+    pub fn http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = Some(http);
+        self
+    }
+
+    /// Sends requests through the given [Transport] instead of directly over HTTP.
+    /// This is mostly useful for testing; see [vcr][crate::transport] for an example.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Decides which failed requests are retried. Defaults to [ExponentialBackoff].
+    pub fn retry_policy(mut self, policy: impl RetryPolicy + 'static) -> Self {
+        self.retry_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Bounds how many requests the client, and all of its clones, will have in flight at once.
+    /// Further requests wait for a slot. Defaults to [DEFAULT_MAX_CONCURRENT_REQUESTS].
+    /// Building the client fails if `max` is 0.
+    pub fn max_concurrent_requests(mut self, max: usize) -> Self {
+        self.max_concurrent_requests = Some(max);
+        self
+    }
+
+    /// Sets how long idle connections are kept open for reuse. [None] keeps them open forever.
+    /// Defaults to 90 seconds. Can not be combined with [http_client][Self::http_client].
+    pub fn pool_idle_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.pool_idle_timeout = Some(timeout.into());
+        self
+    }
+
+    /// Sets how many idle connections are kept open for reuse. Defaults to no limit.
+    /// Can not be combined with [http_client][Self::http_client].
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Enables TCP keepalive on connections, probing idle connections at the given interval.
+    /// Disabled by default. Can not be combined with [http_client][Self::http_client].
+    pub fn tcp_keepalive(mut self, interval: Duration) -> Self {
+        self.tcp_keepalive = Some(interval);
+        self
+    }
+
+    fn build_http_client(&self) -> Result<reqwest::Client, Error> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(interval) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(interval);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Creates a client from the given bearer token. This does not verify that this is a valid token.
+    ///
+    /// Fails if the HTTP client cannot be created, e.g. because the TLS backend could not be
+    /// initialized, if connection options were set along with an
+    /// [HTTP client][Self::http_client], or if no requests at all are allowed at a time.
+    pub fn build_with_token(self, tok: impl Into<String>) -> Result<Client, Error> {
+        if self.max_concurrent_requests == Some(0) {
+            return Err(Error::InvalidConfiguration {
+                reason: "a client must be allowed at least one request at a time".into(),
+                trace: Trace::capture(),
+            });
+        }
+        let has_connection_options = self.pool_idle_timeout.is_some()
+            || self.pool_max_idle_per_host.is_some()
+            || self.tcp_keepalive.is_some();
+        let client = match &self.http {
+            Some(_) if has_connection_options => {
+                return Err(Error::InvalidConfiguration {
+                    reason: "connection options can not be applied to a given HTTP client".into(),
+                    trace: Trace::capture(),
+                });
+            }
+            Some(http) => http.clone(),
+            None => self.build_http_client()?,
+        };
+        Ok(self.finish(client, tok.into()))
+    }
+
+    fn finish(self, client: reqwest::Client, tok: String) -> Client {
+        let transport = self.transport.unwrap_or_else(|| Arc::new(client.clone()));
+        let retry_policy = self.retry_policy.unwrap_or_else(|| Arc::new(ExponentialBackoff::default()));
+        let limiter = Arc::new(Semaphore::new(self.max_concurrent_requests.unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS)));
+        Client {
+            bearer_token: tok,
+            client,
+            transport,
+            retry_policy,
+            limiter,
+            options: RequestOptions::default(),
+        }
+    }
+
+    /// Creates a client by exchanging the given client credentials for a bearer token.
+    pub async fn build_with_credentials(self, client_id: impl AsRef<str>, client_secret: impl AsRef<str>) -> Result<Client, Error> {
+        let mut client = self.build_with_token(String::new())?;
+        let form = [
+            ("client_id", client_id.as_ref()),
+            ("client_secret", client_secret.as_ref()),
+            ("grant_type", "client_credentials")
+        ];
+
+        let value: serde_json::Value = client.send(client.client.post(endpoint!("/token")).form(&form)).await?;
+        let token = value.get("access_token")
+            .and_then(|t| t.as_str())
+            .ok_or_else(|| Error::UnexpectedResponse {
+                status: reqwest::StatusCode::OK,
+                reason: "token response did not contain an access_token".into(),
+                trace: Trace::capture(),
+            })?;
+        client.bearer_token = format!("Bearer {}", token);
+        Ok(client)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::test::init_env;
+    use crate::transport::build_response;
+    use futures::future::BoxFuture;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::options::CancellationToken;
+
+    /// Answers every request after a short delay, keeping track of how many are in flight.
+    #[derive(Debug, Default)]
+    struct SlowServer {
+        in_flight: AtomicUsize,
+        most_in_flight: AtomicUsize,
+    }
+
+    impl Transport for Arc<SlowServer> {
+        fn execute(&self, _request: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response, Error>> {
+            Box::pin(async move {
+                let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.most_in_flight.fetch_max(now, Ordering::SeqCst);
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                build_response(200, &[], br#"{"data":{"id":"1","type":"story","attributes":{"title":"t"}}}"#.to_vec())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_respect_concurrency_limit() {
+        let server = Arc::new(SlowServer::default());
+        let client = Client::builder()
+            .transport(server.clone())
+            .max_concurrent_requests(3)
+            .build_with_token("Bearer t")
+            .unwrap();
+
+        let stories = client.batch_stories(0..20).await;
+        assert!(stories.iter().all(Result::is_ok));
+        assert_eq!(server.most_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[derive(Debug)]
+    struct TokenlessServer;
+
+    impl Transport for TokenlessServer {
+        fn execute(&self, _request: reqwest::Request) -> BoxFuture<'_, Result<reqwest::Response, Error>> {
+            Box::pin(futures::future::ready(build_response(200, &[], br#"{"token_type":"Bearer"}"#.to_vec())))
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_token_is_an_error() {
+        let res = Client::builder()
+            .transport(TokenlessServer)
+            .build_with_credentials("id", "secret")
+            .await;
+        assert!(matches!(res, Err(Error::UnexpectedResponse { .. })));
+    }
+
+    #[test]
+    fn builds_with_pool_options() {
+        let client = Client::builder()
+            .pool_idle_timeout(Duration::from_secs(30))
+            .pool_max_idle_per_host(4)
+            .tcp_keepalive(Duration::from_secs(60))
+            .build_with_token("Bearer t")
+            .unwrap();
+        assert_eq!(client.bearer_token(), "Bearer t");
+    }
+
+    #[test]
+    fn rejects_zero_concurrent_requests() {
+        let res = Client::builder().max_concurrent_requests(0).build_with_token("Bearer t");
+        assert!(matches!(res, Err(Error::InvalidConfiguration { .. })));
+    }
+
+    #[test]
+    fn rejects_pool_options_with_a_given_http_client() {
+        let res = Client::builder()
+            .http_client(reqwest::Client::new())
+            .pool_max_idle_per_host(4)
+            .build_with_token("Bearer t");
+        assert!(matches!(res, Err(Error::InvalidConfiguration { .. })));
+
+        let res = Client::builder()
+            .tcp_keepalive(Duration::from_secs(60))
+            .http_client(reqwest::Client::new())
+            .build_with_token("Bearer t");
+        assert!(matches!(res, Err(Error::InvalidConfiguration { .. })));
+
+        assert!(Client::builder().http_client(reqwest::Client::new()).build_with_token("Bearer t").is_ok());
+    }
+
+    #[tokio::test]
+    async fn requests_past_their_deadline_fail() {
+        let client = Client::builder()
+            .transport(Arc::new(SlowServer::default()))
+            .build_with_token("Bearer t")
+            .unwrap();
+
+        let hasty = client.with_options(RequestOptions::new().timeout(Duration::from_millis(1)));
+        assert!(matches!(hasty.story(1).await, Err(Error::DeadlineExceeded(_))));
+        assert!(client.story(1).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn cancelled_requests_fail() {
+        let token = CancellationToken::new();
+        let client = Client::builder()
+            .transport(Arc::new(SlowServer::default()))
+            .build_with_token("Bearer t")
+            .unwrap()
+            .with_options(RequestOptions::new().cancellation(token.clone()));
+
+        let (res, _) = futures::join!(client.story(1), async { token.cancel() });
+        assert!(matches!(res, Err(Error::Cancelled(_))));
+        assert!(matches!(client.story(1).await, Err(Error::Cancelled(_))));
+    }
+
+    #[tokio::test]
+    async fn collections_follow_next_links() {
+        let mock = crate::testing::MockClient::new();
+        let chapter = |id: u64| format!(r#"{{"id":"{}","type":"chapter","attributes":{{"chapter_number":{},"title":"c"}}}}"#, id, id);
+        mock.on_get("/stories/1/chapters?page[number]=2", format!(r#"{{"data":[{},{}]}}"#, chapter(2), chapter(3)))
+            .on_get("/stories/1/chapters", format!(r#"{{"data":[{}],"links":{{"next":"{}/stories/1/chapters?page[number]=2"}}}}"#, chapter(1), BASE_URL));
+
+        let chapters = mock.client().story_chapters(1).await.unwrap();
+        let numbers: Vec<u64> = chapters.iter().map(|c| c.attributes.chapter_number).collect();
+        assert_eq!(numbers, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn collections_only_follow_next_links_into_the_api_once() {
+        let mock = crate::testing::MockClient::new();
+        mock.on_get("/stories/1/chapters", r#"{"data":[],"links":{"next":"https://evil.example/steal"}}"#)
+            .on_get("/stories/2/chapters", format!(r#"{{"data":[],"links":{{"next":"{}.evil.example/steal"}}}}"#, BASE_URL))
+            .on_get("/stories/3/chapters", format!(r#"{{"data":[],"links":{{"next":"{}/stories/3/chapters"}}}}"#, BASE_URL));
+        let client = mock.client();
+
+        for id in 1..=3 {
+            assert!(matches!(client.story_chapters(id).await, Err(Error::UnexpectedResponse { .. })));
+        }
+        assert_eq!(mock.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn writes_send_the_changed_attributes() {
+        let mock = crate::testing::MockClient::new();
+        mock.on("POST", "/stories/1/chapters", 201, crate::testing::fixtures::SECOND_CHAPTER)
+            .on("PATCH", "/chapters/12", 200, crate::testing::fixtures::SECOND_CHAPTER);
+        let client = mock.client();
+
+        let changes = ChapterChanges { title: Some("Two".into()), content: Some("[b]Hi[/b]".into()), ..Default::default() };
+        let chapter = client.create_chapter(1, &changes).await.unwrap();
+        assert_eq!(chapter.id, "12");
+        client.update_chapter(12, &ChapterChanges { published: Some(true), ..Default::default() }).await.unwrap();
+
+        let bodies: Vec<serde_json::Value> = mock.requests().iter()
+            .map(|r| serde_json::from_str(r.body.as_deref().unwrap()).unwrap())
+            .collect();
+        assert_eq!(bodies[0], serde_json::json!({"data": {"type": "chapter", "attributes": {"title": "Two", "content": "[b]Hi[/b]"}}}));
+        assert_eq!(bodies[1], serde_json::json!({"data": {"type": "chapter", "id": "12", "attributes": {"published": true}}}));
+    }
 
     #[tokio::test]
     pub async fn grab_token() {
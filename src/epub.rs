@@ -0,0 +1,456 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the [EpubBuilder], which assembles an EPUB 3 book locally from a story and its
+//! chapters. Requires the `epub` feature.
+//!
+//! FimFic can export stories itself, but building the book locally allows custom styling and
+//! regenerating books offline, e.g. from an [archive][crate::archive].
+//!
+//! ```no_run
+//! # async fn run(client: fimapi::client::Client) -> Result<(), Box<dyn std::error::Error>> {
+//! use fimapi::epub::EpubBuilder;
+//!
+//! let story = client.story(1).await?;
+//! let mut chapters = Vec::new();
+//! for listed in client.story_chapters(1).await? {
+//!     chapters.push(client.chapter(listed.id.parse()?).await?);
+//! }
+//!
+//! let book = EpubBuilder::new(&story)
+//!     .author("Mock Author")
+//!     .chapters(&chapters)
+//!     .stylesheet("p { text-indent: 1em; }")
+//!     .build()?;
+//! std::fs::write("story.epub", book)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::bbcode;
+use crate::model::{Chapter, Story};
+use crate::util::escape_html as escape;
+use std::io::{self, Cursor, Seek, Write};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// The stylesheet used unless another is given.
+pub const DEFAULT_STYLESHEET: &str = "body { font-family: serif; line-height: 1.4; }
+h1 { text-align: center; }
+p { margin: 0 0 0.8em 0; }
+.cover { text-align: center; }
+.cover img { max-width: 100%; max-height: 100%; }
+";
+
+/// The errors which can occur while building an EPUB.
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum EpubError {
+    /// Writing the archive failed.
+    #[error("could not write archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    /// Writing to the destination failed.
+    #[error("could not write book: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Builds an EPUB 3 book from a [Story] and its [Chapter]s.
+///
+/// Chapters are ordered by their chapter number and should include their text, i.e. be
+/// fetched with [Client::chapter][crate::client::Client::chapter].
+#[derive(Debug, Clone)]
+pub struct EpubBuilder<'a> {
+    story: &'a Story,
+    chapters: Vec<&'a Chapter>,
+    author: Option<String>,
+    language: String,
+    stylesheet: String,
+    cover: Option<(Vec<u8>, String)>,
+}
+
+impl<'a> EpubBuilder<'a> {
+    /// Starts a book for the given story, without any chapters.
+    pub fn new(story: &'a Story) -> Self {
+        EpubBuilder {
+            story,
+            chapters: Vec::new(),
+            author: None,
+            language: "en".into(),
+            stylesheet: DEFAULT_STYLESHEET.into(),
+            cover: None,
+        }
+    }
+
+    /// Adds a chapter.
+    pub fn chapter(mut self, chapter: &'a Chapter) -> Self {
+        self.chapters.push(chapter);
+        self
+    }
+
+    /// Adds several chapters.
+    pub fn chapters(mut self, chapters: impl IntoIterator<Item = &'a Chapter>) -> Self {
+        self.chapters.extend(chapters);
+        self
+    }
+
+    /// Sets the name of the author. Stories only reference their author by id, so the name
+    /// has to be given separately.
+    pub fn author(mut self, name: impl Into<String>) -> Self {
+        self.author = Some(name.into());
+        self
+    }
+
+    /// Sets the language of the book, as a language tag. Defaults to `en`.
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.language = language.into();
+        self
+    }
+
+    /// Replaces the [DEFAULT_STYLESHEET].
+    pub fn stylesheet(mut self, css: impl Into<String>) -> Self {
+        self.stylesheet = css.into();
+        self
+    }
+
+    /// Sets the cover image, with its media type, e.g. `image/png`.
+    pub fn cover(mut self, image: Vec<u8>, media_type: impl Into<String>) -> Self {
+        self.cover = Some((image, media_type.into()));
+        self
+    }
+
+    /// Builds the book in memory.
+    pub fn build(&self) -> Result<Vec<u8>, EpubError> {
+        Ok(self.write_to(Cursor::new(Vec::new()))?.into_inner())
+    }
+
+    /// Writes the book to the given destination and returns it.
+    pub fn write_to<W: Write + Seek>(&self, dest: W) -> Result<W, EpubError> {
+        let mut zip = ZipWriter::new(dest);
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        // The mimetype must come first and be uncompressed.
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(b"application/epub+zip")?;
+        zip.start_file("META-INF/container.xml", deflated)?;
+        zip.write_all(CONTAINER.as_bytes())?;
+
+        let mut chapters = self.chapters.clone();
+        chapters.sort_by_key(|c| c.attributes.chapter_number);
+
+        zip.start_file("OEBPS/content.opf", deflated)?;
+        zip.write_all(self.package(&chapters).as_bytes())?;
+        zip.start_file("OEBPS/nav.xhtml", deflated)?;
+        zip.write_all(self.nav(&chapters).as_bytes())?;
+        zip.start_file("OEBPS/style.css", deflated)?;
+        zip.write_all(self.stylesheet.as_bytes())?;
+        zip.start_file("OEBPS/title.xhtml", deflated)?;
+        zip.write_all(self.title_page().as_bytes())?;
+
+        if let Some((image, media_type)) = &self.cover {
+            zip.start_file(format!("OEBPS/cover.{}", extension(media_type)), stored)?;
+            zip.write_all(image)?;
+            zip.start_file("OEBPS/cover.xhtml", deflated)?;
+            let body = format!("<div class=\"cover\"><img src=\"cover.{}\" alt=\"Cover\"/></div>", extension(media_type));
+            zip.write_all(page("Cover", &body).as_bytes())?;
+        }
+
+        for (i, chapter) in chapters.iter().enumerate() {
+            zip.start_file(format!("OEBPS/chapter{}.xhtml", i + 1), deflated)?;
+            zip.write_all(chapter_page(chapter).as_bytes())?;
+        }
+
+        Ok(zip.finish()?)
+    }
+
+    fn package(&self, chapters: &[&Chapter]) -> String {
+        let attrs = &self.story.attributes;
+        let mut metadata = format!(
+            "<dc:identifier id=\"id\">urn:fimfiction:story:{}</dc:identifier>\n<dc:title>{}</dc:title>\n<dc:language>{}</dc:language>\n<meta property=\"dcterms:modified\">{}</meta>\n",
+            escape(&self.story.id), escape(&attrs.title), escape(&self.language), modified(self.story),
+        );
+        if let Some(author) = &self.author {
+            metadata.push_str(&format!("<dc:creator>{}</dc:creator>\n", escape(author)));
+        }
+        if let Some(description) = &attrs.short_description {
+            metadata.push_str(&format!("<dc:description>{}</dc:description>\n", escape(description)));
+        }
+        if let Some(date) = &attrs.date_published {
+            metadata.push_str(&format!("<dc:date>{}</dc:date>\n", escape(date)));
+        }
+
+        let mut manifest = String::from(
+            "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n<item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n<item id=\"title\" href=\"title.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+        );
+        let mut spine = String::new();
+        if let Some((_, media_type)) = &self.cover {
+            metadata.push_str("<meta name=\"cover\" content=\"cover-image\"/>\n");
+            manifest.push_str(&format!(
+                "<item id=\"cover-image\" href=\"cover.{}\" media-type=\"{}\" properties=\"cover-image\"/>\n<item id=\"cover\" href=\"cover.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+                extension(media_type), escape(media_type),
+            ));
+            spine.push_str("<itemref idref=\"cover\"/>\n");
+        }
+        spine.push_str("<itemref idref=\"title\"/>\n");
+        for i in 1..=chapters.len() {
+            manifest.push_str(&format!("<item id=\"chapter{0}\" href=\"chapter{0}.xhtml\" media-type=\"application/xhtml+xml\"/>\n", i));
+            spine.push_str(&format!("<itemref idref=\"chapter{}\"/>\n", i));
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"id\">\n<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{}</metadata>\n<manifest>\n{}</manifest>\n<spine>\n{}</spine>\n</package>\n",
+            metadata, manifest, spine,
+        )
+    }
+
+    fn nav(&self, chapters: &[&Chapter]) -> String {
+        let mut items = String::from("<li><a href=\"title.xhtml\">Title Page</a></li>\n");
+        for (i, chapter) in chapters.iter().enumerate() {
+            items.push_str(&format!("<li><a href=\"chapter{}.xhtml\">{}</a></li>\n", i + 1, escape(&chapter.attributes.title)));
+        }
+        page("Contents", &format!("<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n{}</ol>\n</nav>", items))
+    }
+
+    fn title_page(&self) -> String {
+        let attrs = &self.story.attributes;
+        let mut body = format!("<h1>{}</h1>\n", escape(&attrs.title));
+        if let Some(author) = &self.author {
+            body.push_str(&format!("<p class=\"author\">by {}</p>\n", escape(author)));
+        }
+        match (&attrs.description_html, &attrs.short_description) {
+            (Some(html), _) => body.push_str(&to_xhtml(html)),
+            (None, Some(text)) => body.push_str(&to_xhtml(&bbcode::html::render(text))),
+            (None, None) => {}
+        }
+        page(&attrs.title, &body)
+    }
+}
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#;
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\">\n<head>\n<title>{}</title>\n<link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        escape(title), body,
+    )
+}
+
+fn chapter_page(chapter: &Chapter) -> String {
+    let attrs = &chapter.attributes;
+    let mut body = format!("<h1>{}</h1>\n", escape(&attrs.title));
+    match (&attrs.content_html, &attrs.content) {
+        (Some(html), _) => body.push_str(&to_xhtml(html)),
+        (None, Some(bbcode)) => body.push_str(&to_xhtml(&bbcode::html::render(bbcode))),
+        (None, None) => {}
+    }
+    page(&attrs.title, &body)
+}
+
+/// The EPUB modification date, which must be in UTC without fractional seconds.
+fn modified(story: &Story) -> String {
+    let attrs = &story.attributes;
+    attrs.date_modified.as_deref().and_then(to_utc)
+        .or_else(|| attrs.date_published.as_deref().and_then(to_utc))
+        .unwrap_or_else(|| "1970-01-01T00:00:00Z".into())
+}
+
+/// Converts a timestamp like `2020-06-01T14:00:00+02:00`, as FimFic sends them, to UTC:
+/// `2020-06-01T12:00:00Z`. Returns [None] if it is not in that form.
+fn to_utc(date: &str) -> Option<String> {
+    let number = |at: usize, len: usize| date.get(at..at + len)
+        .filter(|n| n.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|n| n.parse::<i64>().ok());
+    let separators = [(4, "-"), (7, "-"), (10, "T"), (13, ":"), (16, ":")];
+    if separators.iter().any(|&(at, sep)| date.get(at..at + 1) != Some(sep)) {
+        return None;
+    }
+    let (year, month, day) = (number(0, 4)?, number(5, 2)?, number(8, 2)?);
+    let (hour, minute, second) = (number(11, 2)?, number(14, 2)?, number(17, 2)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    // Fractional seconds are dropped.
+    let zone = date[19..].trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match zone.as_bytes() {
+        b"Z" => 0,
+        [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+            let minutes = zone[1..3].parse::<i64>().ok()? * 60 + zone[4..6].parse::<i64>().ok()?;
+            if *sign == b'+' { minutes * 60 } else { -minutes * 60 }
+        }
+        _ => return None,
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    Some(format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60))
+}
+
+/// The number of days from 1970-01-01 to the given date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The date the given number of days after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+fn extension(media_type: &str) -> &str {
+    match media_type {
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/svg+xml" => "svg",
+        "image/webp" => "webp",
+        _ => "png",
+    }
+}
+
+const VOID_ELEMENTS: &[&str] = &["area", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "wbr"];
+
+const ENTITIES: &[(&str, &str)] = &[
+    ("nbsp", "&#160;"), ("mdash", "&#8212;"), ("ndash", "&#8211;"), ("hellip", "&#8230;"),
+    ("lsquo", "&#8216;"), ("rsquo", "&#8217;"), ("ldquo", "&#8220;"), ("rdquo", "&#8221;"),
+    ("copy", "&#169;"), ("shy", "&#173;"),
+];
+
+/// Turns the HTML FimFic renders into well-formed XHTML: void elements are closed and
+/// HTML-only entities are replaced by numeric references.
+fn to_xhtml(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(i) = rest.find(['<', '&']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with('&') {
+            let name = rest[1..].find(';')
+                .map(|end| &rest[1..=end])
+                .filter(|n| n.chars().all(|c| c.is_ascii_alphanumeric() || c == '#'));
+            match name {
+                Some(n) if n.starts_with('#') || ["amp", "lt", "gt", "quot", "apos"].contains(&n) => {
+                    out.push_str(&rest[..n.len() + 2]);
+                }
+                Some(n) => match ENTITIES.iter().find(|(e, _)| *e == n) {
+                    Some((_, replacement)) => out.push_str(replacement),
+                    None => out.push_str(&format!("&amp;{};", n)),
+                },
+                None => {
+                    out.push_str("&amp;");
+                    rest = &rest[1..];
+                    continue;
+                }
+            }
+            rest = &rest[name.map_or(1, |n| n.len() + 2)..];
+            continue;
+        }
+
+        let end = tag_end(rest).unwrap_or(rest.len());
+        let tag = &rest[..end];
+        let name: String = tag[1..].chars().take_while(|c| c.is_ascii_alphanumeric()).collect();
+        if VOID_ELEMENTS.contains(&name.to_ascii_lowercase().as_str()) && tag.ends_with('>') && !tag.ends_with("/>") {
+            out.push_str(&tag[..tag.len() - 1]);
+            out.push_str("/>");
+        } else {
+            out.push_str(tag);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Finds the end of the tag at the start of `s`, skipping `>` inside quoted attributes.
+fn tag_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixtures;
+    use std::io::Read;
+    use zip::ZipArchive;
+
+    fn read(book: &[u8], name: &str) -> String {
+        let mut zip = ZipArchive::new(Cursor::new(book)).unwrap();
+        let mut file = zip.by_name(name).unwrap();
+        let mut s = String::new();
+        file.read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn builds_a_book() {
+        let story: Story = serde_json::from_str::<crate::model::Document<Story>>(fixtures::STORY).unwrap().data;
+        let first: Chapter = serde_json::from_str::<crate::model::Document<Chapter>>(fixtures::CHAPTER).unwrap().data;
+        let second: Chapter = serde_json::from_str::<crate::model::Document<Chapter>>(fixtures::SECOND_CHAPTER).unwrap().data;
+
+        let book = EpubBuilder::new(&story)
+            .author("Mock Author")
+            .chapter(&second)
+            .chapter(&first)
+            .cover(b"png".to_vec(), "image/png")
+            .build()
+            .unwrap();
+
+        let mut zip = ZipArchive::new(Cursor::new(&book[..])).unwrap();
+        assert_eq!(zip.by_index(0).unwrap().name(), "mimetype");
+        assert_eq!(zip.by_index(0).unwrap().compression(), CompressionMethod::Stored);
+
+        let opf = read(&book, "OEBPS/content.opf");
+        assert!(opf.contains("<dc:title>The Mock Story</dc:title>"));
+        assert!(opf.contains("<dc:creator>Mock Author</dc:creator>"));
+        assert!(opf.contains("<meta property=\"dcterms:modified\">2020-06-01T12:00:00Z</meta>"));
+        assert!(opf.contains("properties=\"cover-image\""));
+
+        assert!(read(&book, "OEBPS/chapter1.xhtml").contains("<p><b>Once</b> upon a time.</p>"));
+        assert!(read(&book, "OEBPS/nav.xhtml").contains("<a href=\"chapter2.xhtml\">The End</a>"));
+    }
+
+    #[test]
+    fn converts_modification_dates_to_utc() {
+        assert_eq!(to_utc("2020-06-01T14:00:00+02:00").as_deref(), Some("2020-06-01T12:00:00Z"));
+        assert_eq!(to_utc("2020-01-01T00:30:00.250+01:00").as_deref(), Some("2019-12-31T23:30:00Z"));
+        assert_eq!(to_utc("2020-02-28T22:00:00-05:00").as_deref(), Some("2020-02-29T03:00:00Z"));
+        assert_eq!(to_utc("2020-06-01T12:00:00Z").as_deref(), Some("2020-06-01T12:00:00Z"));
+        assert_eq!(to_utc("2020-06-01T12:00:00"), None);
+        assert_eq!(to_utc("2020-06-01T12:00:0é+00:00"), None);
+        assert_eq!(to_utc("yesterday"), None);
+    }
+
+    #[test]
+    fn converts_html_to_xhtml() {
+        assert_eq!(to_xhtml("a<br>b<hr class=\"x>y\">c<br/>"), "a<br/>b<hr class=\"x>y\"/>c<br/>");
+        assert_eq!(to_xhtml("a&nbsp;b &amp; c &#8212; &bogus; & d"), "a&#160;b &amp; c &#8212; &amp;bogus; &amp; d");
+        assert_eq!(to_xhtml("<img src=\"x.png\" alt=\"\">"), "<img src=\"x.png\" alt=\"\"/>");
+    }
+}
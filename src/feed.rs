@@ -0,0 +1,320 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains support for FimFiction's RSS and Atom feeds, which report site activity without
+//! needing an OAuth token. Requires the `feed` feature.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), fimapi::response::Error> {
+//! use fimapi::client::Client;
+//! use fimapi::feed::{fetch, Feed};
+//!
+//! // Feeds are public, so the token is never sent.
+//! let client = Client::from_token("");
+//! for item in fetch(&client, &Feed::LatestStories).await? {
+//!     println!("{} ({:?})", item.title, item.story_id);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::Client;
+use crate::model::{Chapter, ChapterAttributes, Relationship, RelationshipData, Resource, ResourceId, Story, StoryAttributes};
+use crate::response::Error;
+use crate::response::error::Trace;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::StatusCode;
+
+/// The site the feeds are served from.
+pub const FEED_BASE_URL: &str = "https://www.fimfiction.net/rss";
+
+/// A feed of site activity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Feed {
+    /// The most recently updated stories on the site.
+    LatestStories,
+    /// The stories by the user with the given id.
+    UserStories(u64),
+    /// The unread chapters of a user's tracked stories. This feed is private, so it is
+    /// identified by the full URL shown in the user's account settings.
+    UnreadChapters(String),
+    /// Any other feed, by URL.
+    Url(String),
+}
+
+impl Feed {
+    /// The URL this feed is served from.
+    pub fn url(&self) -> String {
+        match self {
+            Feed::LatestStories => format!("{}/stories/latest", FEED_BASE_URL),
+            Feed::UserStories(id) => format!("{}/user/{}/stories", FEED_BASE_URL, id),
+            Feed::UnreadChapters(url) | Feed::Url(url) => url.clone(),
+        }
+    }
+}
+
+/// A single entry of a feed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeedItem {
+    /// The title of the entry, usually the story or chapter title.
+    pub title: String,
+    /// The link to the story or chapter on the site.
+    pub link: String,
+    /// The summary of the entry, as HTML.
+    pub description: Option<String>,
+    /// The name of the author.
+    pub author: Option<String>,
+    /// When the entry was published, as given by the feed.
+    pub published: Option<String>,
+    /// The id of the story, parsed from the link.
+    pub story_id: Option<u64>,
+    /// The number of the chapter, parsed from the link, if the entry is a chapter.
+    pub chapter_number: Option<u64>,
+    /// The id of the chapter, parsed from a `/chapter/<id>` link or guid, if the feed gives one.
+    pub chapter_id: Option<u64>,
+}
+
+impl FeedItem {
+    /// Converts the entry into a [Story], with the few attributes a feed carries.
+    /// Returns [None] if the link does not point at a story.
+    pub fn to_story(&self) -> Option<Story> {
+        Some(Resource {
+            id: self.story_id?.to_string(),
+            kind: "story".into(),
+            attributes: StoryAttributes {
+                title: self.title.clone(),
+                description_html: self.description.clone(),
+                date_published: self.published.clone(),
+                published: true,
+                ..Default::default()
+            },
+            relationships: Default::default(),
+        })
+    }
+
+    /// Converts the entry into a [Chapter], with the few attributes a feed carries and its
+    /// `story` relationship. Returns [None] if the entry is not a chapter, or if the feed does
+    /// not give the chapter's id; fetch the chapters of the story to find it instead.
+    pub fn to_chapter(&self) -> Option<Chapter> {
+        let story = ResourceId { id: self.story_id?.to_string(), kind: "story".into() };
+        Some(Resource {
+            id: self.chapter_id?.to_string(),
+            kind: "chapter".into(),
+            attributes: ChapterAttributes {
+                chapter_number: self.chapter_number?,
+                title: self.title.clone(),
+                published: true,
+                date_published: self.published.clone(),
+                ..Default::default()
+            },
+            relationships: std::iter::once(("story".to_string(), Relationship {
+                data: RelationshipData::One(Some(story)),
+            })).collect(),
+        })
+    }
+}
+
+/// Fetches and parses a feed. The client's token is not sent.
+pub async fn fetch(client: &Client, feed: &Feed) -> Result<Vec<FeedItem>, Error> {
+    let body = client.download(&feed.url()).await?;
+    parse(&String::from_utf8_lossy(&body))
+}
+
+/// Parses an RSS or Atom document into its entries.
+pub fn parse(xml: &str) -> Result<Vec<FeedItem>, Error> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut items = Vec::new();
+    let mut current: Option<FeedItem> = None;
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        let event = reader.read_event(&mut buf).map_err(|e| invalid(&reader, e))?;
+        match event {
+            Event::Start(e) => {
+                let name = e.local_name().to_vec();
+                if name == b"item" || name == b"entry" {
+                    current = Some(FeedItem::default());
+                }
+                path.push(name);
+            }
+            Event::Empty(e) => {
+                // Atom links are empty elements: <link rel="alternate" href="..."/>
+                if let (Some(item), b"link") = (current.as_mut(), e.local_name()) {
+                    let mut href = None;
+                    let mut alternate = true;
+                    for attr in e.attributes().flatten() {
+                        let value = attr.unescape_and_decode_value(&reader).map_err(|e| invalid(&reader, e))?;
+                        match attr.key {
+                            b"href" => href = Some(value),
+                            b"rel" => alternate = value == "alternate",
+                            _ => {}
+                        }
+                    }
+                    if let Some(href) = href.filter(|_| alternate || item.link.is_empty()) {
+                        item.link = href;
+                    }
+                }
+            }
+            Event::Text(e) => {
+                let text = e.unescape_and_decode(&reader).map_err(|e| invalid(&reader, e))?;
+                set_field(current.as_mut(), &path, text);
+            }
+            Event::CData(e) => {
+                set_field(current.as_mut(), &path, String::from_utf8_lossy(&e).into_owned());
+            }
+            Event::End(e) => {
+                path.pop();
+                if e.local_name() == b"item" || e.local_name() == b"entry" {
+                    if let Some(mut item) = current.take() {
+                        let (story_id, chapter_number) = parse_link(&item.link);
+                        item.story_id = story_id;
+                        item.chapter_number = chapter_number;
+                        item.chapter_id = item.chapter_id.or_else(|| parse_chapter_id(&item.link));
+                        items.push(item);
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(items)
+}
+
+fn set_field(item: Option<&mut FeedItem>, path: &[Vec<u8>], text: String) {
+    let item = match item {
+        Some(item) => item,
+        None => return,
+    };
+    let (field, parent) = match path {
+        [.., parent, field] => (field.as_slice(), parent.as_slice()),
+        _ => return,
+    };
+    match (parent, field) {
+        (_, b"title") => item.title = text,
+        (_, b"link") => item.link = text,
+        (_, b"description") | (_, b"summary") | (_, b"content") if item.description.is_none() => {
+            item.description = Some(text)
+        }
+        (b"author", b"name") | (_, b"creator") => item.author = Some(text),
+        (b"item", b"author") => item.author = Some(text),
+        (_, b"pubDate") | (_, b"published") => item.published = Some(text),
+        (_, b"updated") if item.published.is_none() => item.published = Some(text),
+        (_, b"guid") | (b"entry", b"id") => item.chapter_id = parse_chapter_id(&text),
+        _ => {}
+    }
+}
+
+/// Parses the story id and chapter number out of a link like
+/// `https://www.fimfiction.net/story/1234/2/story-title/chapter-title`.
+fn parse_link(link: &str) -> (Option<u64>, Option<u64>) {
+    let mut segments = link.split('/').skip_while(|s| *s != "story").skip(1);
+    let story_id = segments.next().and_then(|s| s.parse().ok());
+    let chapter_number = story_id.and(segments.next()).and_then(|s| s.parse().ok());
+    (story_id, chapter_number)
+}
+
+/// Parses the chapter id out of a link like `https://www.fimfiction.net/chapter/5678`.
+fn parse_chapter_id(link: &str) -> Option<u64> {
+    link.split('/').skip_while(|s| *s != "chapter").nth(1).and_then(|s| s.parse().ok())
+}
+
+fn invalid(reader: &Reader<&[u8]>, e: quick_xml::Error) -> Error {
+    Error::UnexpectedResponse {
+        status: StatusCode::OK,
+        reason: format!("invalid feed at byte {}: {}", reader.buffer_position(), e),
+        trace: Trace::capture(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClient;
+
+    const RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/">
+            <channel>
+                <title>Latest Stories</title>
+                <link>https://www.fimfiction.net/</link>
+                <item>
+                    <title>The Mock Story</title>
+                    <link>https://www.fimfiction.net/story/1/the-mock-story</link>
+                    <description><![CDATA[<p>A <b>story</b> that never was.</p>]]></description>
+                    <dc:creator>Mock Author</dc:creator>
+                    <pubDate>Fri, 01 May 2020 12:00:00 +0000</pubDate>
+                </item>
+                <item>
+                    <title>The End &amp; After</title>
+                    <link>https://www.fimfiction.net/story/1/2/the-mock-story/the-end</link>
+                    <guid>https://www.fimfiction.net/chapter/12</guid>
+                </item>
+            </channel>
+        </rss>"#;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+        <feed xmlns="http://www.w3.org/2005/Atom">
+            <title>Unread Chapters</title>
+            <entry>
+                <title>The Beginning</title>
+                <link rel="alternate" href="https://www.fimfiction.net/story/1/1/the-mock-story/the-beginning"/>
+                <author><name>Mock Author</name></author>
+                <updated>2020-05-01T12:00:00+00:00</updated>
+                <summary>Once upon a time.</summary>
+            </entry>
+        </feed>"#;
+
+    #[test]
+    fn parses_rss() {
+        let items = parse(RSS).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].title, "The Mock Story");
+        assert_eq!(items[0].description.as_deref(), Some("<p>A <b>story</b> that never was.</p>"));
+        assert_eq!(items[0].author.as_deref(), Some("Mock Author"));
+        assert_eq!((items[0].story_id, items[0].chapter_number), (Some(1), None));
+        let story = items[0].to_story().unwrap();
+        assert_eq!(story.id, "1");
+        assert_eq!(story.attributes.description_html.as_deref(), Some("<p>A <b>story</b> that never was.</p>"));
+        assert_eq!(story.attributes.short_description, None);
+        assert!(items[0].to_chapter().is_none());
+
+        assert_eq!(items[1].title, "The End & After");
+        let chapter = items[1].to_chapter().unwrap();
+        assert_eq!(chapter.id, "12");
+        assert_eq!(chapter.attributes.chapter_number, 2);
+        assert_eq!(chapter.related_id("story"), Some("1"));
+    }
+
+    #[test]
+    fn parses_atom() {
+        let items = parse(ATOM).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].author.as_deref(), Some("Mock Author"));
+        assert_eq!(items[0].published.as_deref(), Some("2020-05-01T12:00:00+00:00"));
+        assert_eq!((items[0].story_id, items[0].chapter_number), (Some(1), Some(1)));
+        // Without a chapter id there is no way to identify the chapter.
+        assert_eq!(items[0].chapter_id, None);
+        assert!(items[0].to_chapter().is_none());
+    }
+
+    #[test]
+    fn rejects_broken_feeds() {
+        assert!(matches!(parse("<rss><channel><item></channel></rss>"), Err(Error::UnexpectedResponse { .. })));
+    }
+
+    #[tokio::test]
+    async fn fetches_without_a_token() {
+        let mock = MockClient::new();
+        mock.on_get(&Feed::UserStories(2).url(), RSS);
+        let items = fetch(&mock.client(), &Feed::UserStories(2)).await.unwrap();
+        assert_eq!(items.len(), 2);
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].header("Authorization"), None);
+    }
+}
@@ -0,0 +1,383 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains [Fimfarchive], which reads a [Fimfarchive](https://www.fimfiction.net/user/116950/Fimfarchive)
+//! release offline. Requires the `fimfarchive` feature.
+//!
+//! A release is an `index.json` describing every story, plus an EPUB per story. The index is
+//! read into the same [Story], [Chapter] and [User] models the [Client][crate::client::Client]
+//! returns, so analysis written against the API also runs against the archive. Chapter texts
+//! are only read from the EPUBs on request, with [Fimfarchive::load_chapters].
+//!
+//! Releases are snapshots, so [SyncState::from_fimfarchive][crate::sync::SyncState::from_fimfarchive]
+//! turns one into the starting point of a [sync][crate::sync::sync], which then only fetches
+//! what changed since the release.
+//!
+//! ```no_run
+//! # async fn run(client: fimapi::client::Client) -> Result<(), Box<dyn std::error::Error>> {
+//! use fimapi::fimfarchive::Fimfarchive;
+//! use fimapi::sync::{sync, SyncState};
+//!
+//! let mut archive = Fimfarchive::open("fimfarchive-20200601.zip")?;
+//! let mut stories = archive.stories()?;
+//! archive.load_chapters(&mut stories[0])?;
+//!
+//! let state = SyncState::from_fimfarchive(&stories);
+//! let diff = sync(&client, &state, state.story_ids()).await?;
+//! println!("{} stories changed since the release", diff.stories.len());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::bbcode;
+use crate::bbcode::import::{tokenize, Token};
+use crate::model::{Chapter, ChapterAttributes, Relationship, RelationshipData, Resource, ResourceId, Story, StoryAttributes, User, UserAttributes};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// The errors which can occur while reading a release.
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum FimfarchiveError {
+    /// Reading a file failed.
+    #[error("could not read {}: {source}", path.display())]
+    Io {
+        /// The file which could not be read.
+        path: PathBuf,
+        /// The underlying error.
+        source: io::Error,
+    },
+    /// Reading the release or a story EPUB as a zip failed.
+    #[error("could not read archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    /// The index could not be parsed.
+    #[error("could not parse index: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A story has no EPUB in the release.
+    #[error("story {0} has no story file")]
+    MissingFile(String),
+    /// A story EPUB is not laid out as expected.
+    #[error("invalid story file {path}: {reason}")]
+    InvalidEpub {
+        /// The path of the EPUB in the release.
+        path: String,
+        /// What is wrong with it.
+        reason: String,
+    },
+}
+
+/// A story from a release, with its author and chapters.
+#[derive(Debug, Clone)]
+pub struct ArchivedStory {
+    /// The story, with its `author` and `tags` relationships.
+    pub story: Story,
+    /// The author, if the release knows them. Only the name is filled in.
+    pub author: Option<User>,
+    /// The chapters, ordered by chapter number. Their text is only present once loaded with
+    /// [Fimfarchive::load_chapters].
+    pub chapters: Vec<Chapter>,
+    /// The path of the story EPUB in the release.
+    pub path: Option<String>,
+}
+
+/// A Fimfarchive release, either as the zip it is distributed as or extracted to a directory.
+#[derive(Debug)]
+pub struct Fimfarchive {
+    path: PathBuf,
+    source: Source,
+}
+
+#[derive(Debug)]
+enum Source {
+    Dir,
+    Zip { zip: ZipArchive<File>, prefix: String },
+}
+
+impl Fimfarchive {
+    /// Opens the release at the given path, which is either the release zip or the directory
+    /// it was extracted to. Nothing is read until [stories][Self::stories] is called.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, FimfarchiveError> {
+        let path = path.as_ref().to_owned();
+        if path.is_dir() {
+            return Ok(Fimfarchive { path, source: Source::Dir });
+        }
+        let file = File::open(&path).map_err(|source| FimfarchiveError::Io { path: path.clone(), source })?;
+        let zip = ZipArchive::new(file)?;
+        // Releases may keep everything in a top-level directory.
+        let prefix = zip.file_names()
+            .filter_map(|n| n.strip_suffix("index.json"))
+            .filter(|p| p.is_empty() || p.ends_with('/'))
+            .min_by_key(|p| p.len())
+            .unwrap_or_default()
+            .to_owned();
+        Ok(Fimfarchive { path, source: Source::Zip { zip, prefix } })
+    }
+
+    /// Reads every story in the index, ordered by id.
+    pub fn stories(&mut self) -> Result<Vec<ArchivedStory>, FimfarchiveError> {
+        let index: BTreeMap<String, IndexEntry> = serde_json::from_slice(&self.read("index.json")?)?;
+        let mut stories: Vec<_> = index.into_values().map(IndexEntry::into_story).collect();
+        stories.sort_by_key(|s| s.story.id.parse::<u64>().unwrap_or(u64::MAX));
+        Ok(stories)
+    }
+
+    /// Reads the text of every chapter of the story from its EPUB, setting their `content_html`
+    /// and their `content`, converted back to BBCode.
+    ///
+    /// Chapters are matched to the last documents in the EPUB's reading order, since those
+    /// follow the title page.
+    pub fn load_chapters(&mut self, story: &mut ArchivedStory) -> Result<(), FimfarchiveError> {
+        let path = story.path.clone().ok_or_else(|| FimfarchiveError::MissingFile(story.story.id.clone()))?;
+        let mut epub = ZipArchive::new(Cursor::new(self.read(&path)?))?;
+        let invalid = |reason: &str| FimfarchiveError::InvalidEpub { path: path.clone(), reason: reason.into() };
+
+        let container = read_entry(&mut epub, "META-INF/container.xml")?;
+        let opf_path = attr_of(&container, "rootfile", "full-path").ok_or_else(|| invalid("no rootfile in container"))?;
+        let opf = read_entry(&mut epub, &opf_path)?;
+        let base = opf_path.rfind('/').map_or("", |i| &opf_path[..=i]);
+
+        let mut hrefs = HashMap::new();
+        let mut spine = Vec::new();
+        for token in tokenize(&opf) {
+            if let Token::Open { name, attrs, .. } = token {
+                let get = |key: &str| attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+                match name.as_str() {
+                    "item" => if let (Some(id), Some(href)) = (get("id"), get("href")) {
+                        hrefs.insert(id, href);
+                    },
+                    "itemref" => spine.extend(get("idref")),
+                    _ => {}
+                }
+            }
+        }
+        let documents = spine.iter()
+            .map(|id| hrefs.get(id).map(|href| format!("{}{}", base, href)).ok_or_else(|| invalid("spine refers to a missing item")))
+            .collect::<Result<Vec<_>, _>>()?;
+        if documents.len() < story.chapters.len() {
+            return Err(invalid("fewer documents than chapters"));
+        }
+
+        let skip = documents.len() - story.chapters.len();
+        for (chapter, document) in story.chapters.iter_mut().zip(&documents[skip..]) {
+            let html = chapter_body(&read_entry(&mut epub, document)?).to_owned();
+            chapter.attributes.content = Some(bbcode::from_html(&html).to_bbcode());
+            chapter.attributes.content_html = Some(html);
+        }
+        Ok(())
+    }
+
+    /// Reads a file from the release, by its path relative to the release root.
+    fn read(&mut self, name: &str) -> Result<Vec<u8>, FimfarchiveError> {
+        let path = self.path.join(name);
+        match &mut self.source {
+            Source::Dir => std::fs::read(&path).map_err(|source| FimfarchiveError::Io { path, source }),
+            Source::Zip { zip, prefix } => {
+                let mut file = zip.by_name(&format!("{}{}", prefix, name))?;
+                let mut bytes = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut bytes).map_err(|source| FimfarchiveError::Io { path, source })?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+fn read_entry<R: Read + Seek>(zip: &mut ZipArchive<R>, name: &str) -> Result<String, FimfarchiveError> {
+    let mut file = zip.by_name(name)?;
+    let mut s = String::new();
+    file.read_to_string(&mut s).map_err(|source| FimfarchiveError::Io { path: name.into(), source })?;
+    Ok(s)
+}
+
+/// Finds the value of an attribute on the first element with the given name.
+fn attr_of(xml: &str, element: &str, attr: &str) -> Option<String> {
+    tokenize(xml).into_iter().find_map(|token| match token {
+        Token::Open { name, attrs, .. } if name == element => {
+            attrs.into_iter().find(|(k, _)| k == attr).map(|(_, v)| v)
+        }
+        _ => None,
+    })
+}
+
+/// Returns the contents of the body of a chapter page, without the chapter heading.
+fn chapter_body(page: &str) -> &str {
+    let lower = page.to_ascii_lowercase();
+    let start = lower.find("<body")
+        .and_then(|i| lower[i..].find('>').map(|end| i + end + 1))
+        .unwrap_or(0);
+    let end = lower.rfind("</body>").filter(|&end| end >= start).unwrap_or(page.len());
+    let mut body = page[start..end].trim();
+
+    let lower = body.to_ascii_lowercase();
+    if lower.starts_with("<h1") {
+        if let Some(close) = lower.find("</h1>") {
+            body = body[close + "</h1>".len()..].trim_start();
+        }
+    }
+    body
+}
+
+/// A story as it appears in the index.
+#[derive(Debug, Deserialize)]
+struct IndexEntry {
+    id: u64,
+    #[serde(default)]
+    author: Option<IndexUser>,
+    #[serde(default)]
+    tags: Vec<IndexTag>,
+    #[serde(default)]
+    chapters: Vec<IndexChapter>,
+    #[serde(default)]
+    archive: Option<IndexArchive>,
+    #[serde(flatten)]
+    attributes: StoryAttributes,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexUser {
+    id: u64,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexTag {
+    id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexChapter {
+    id: u64,
+    #[serde(flatten)]
+    attributes: ChapterAttributes,
+}
+
+#[derive(Debug, Deserialize)]
+struct IndexArchive {
+    path: String,
+}
+
+fn resource_id(kind: &str, id: u64) -> ResourceId {
+    ResourceId { id: id.to_string(), kind: kind.into() }
+}
+
+impl IndexEntry {
+    fn into_story(self) -> ArchivedStory {
+        let story_id = self.id;
+        let mut relationships = BTreeMap::new();
+        if let Some(author) = &self.author {
+            relationships.insert("author".to_owned(), Relationship { data: RelationshipData::One(Some(resource_id("user", author.id))) });
+        }
+        let tags = self.tags.iter().map(|t| resource_id("story_tag", t.id)).collect();
+        relationships.insert("tags".to_owned(), Relationship { data: RelationshipData::Many(tags) });
+
+        let mut chapters: Vec<Chapter> = self.chapters.into_iter()
+            .map(|c| {
+                let mut relationships = BTreeMap::new();
+                relationships.insert("story".to_owned(), Relationship { data: RelationshipData::One(Some(resource_id("story", story_id))) });
+                Resource { id: c.id.to_string(), kind: "chapter".into(), attributes: c.attributes, relationships }
+            })
+            .collect();
+        chapters.sort_by_key(|c| c.attributes.chapter_number);
+
+        ArchivedStory {
+            story: Resource { id: story_id.to_string(), kind: "story".into(), attributes: self.attributes, relationships },
+            author: self.author.map(|a| Resource {
+                id: a.id.to_string(),
+                kind: "user".into(),
+                attributes: UserAttributes { name: a.name, ..Default::default() },
+                relationships: BTreeMap::new(),
+            }),
+            chapters,
+            path: self.archive.map(|a| a.path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::CompletionStatus;
+    use crate::sync::SyncState;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    const INDEX: &str = r#"{
+"9": {"id": 9, "title": "Second Story", "date_modified": "2020-02-01T00:00:00+00:00", "archive": {"path": "epub/b/second-9.epub"}, "chapters": []},
+"3": {"id": 3, "title": "First Story", "short_description": "Ponies.", "completion_status": "complete", "content_rating": "everyone", "date_modified": "2020-01-01T00:00:00+00:00", "num_words": 6, "author": {"id": 2, "name": "Mock Author"}, "tags": [{"id": 7, "name": "Comedy", "type": "genre"}], "archive": {"path": "epub/a/first-3.epub", "format": "epub"}, "chapters": [{"id": 32, "chapter_number": 2, "title": "The End", "num_words": 2, "date_modified": "2020-01-01T00:00:00+00:00"}, {"id": 31, "chapter_number": 1, "title": "Once", "num_words": 4, "published": true, "date_modified": "2019-12-01T00:00:00+00:00"}]}
+}
+"#;
+
+    fn epub() -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let files = [
+            ("META-INF/container.xml", r#"<?xml version="1.0"?><container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#),
+            ("OEBPS/content.opf", r#"<package><manifest><item id="title" href="title.html"/><item id="c1" href="chapter-1.html"/><item id="c2" href="chapter-2.html"/></manifest><spine><itemref idref="title"/><itemref idref="c1"/><itemref idref="c2"/></spine></package>"#),
+            ("OEBPS/title.html", "<html><body><h1>First Story</h1></body></html>"),
+            ("OEBPS/chapter-1.html", "<html><head><title>Once</title></head><body class=\"c\">\n<h1>Once</h1>\n<p><b>Once</b> upon a time.</p>\n</body></html>"),
+            ("OEBPS/chapter-2.html", "<html><body><h1>The End</h1><p>The end.</p></body></html>"),
+        ];
+        for (name, contents) in files.iter() {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn release(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("fimapi-fimfarchive-{}-{}.zip", std::process::id(), name));
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("fimfarchive-20200601/index.json", FileOptions::default()).unwrap();
+        zip.write_all(INDEX.as_bytes()).unwrap();
+        zip.start_file("fimfarchive-20200601/epub/a/first-3.epub", FileOptions::default()).unwrap();
+        zip.write_all(&epub()).unwrap();
+        zip.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_the_index() {
+        let path = release("index");
+        let stories = Fimfarchive::open(&path).unwrap().stories().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(stories.iter().map(|s| s.story.id.as_str()).collect::<Vec<_>>(), ["3", "9"]);
+        let first = &stories[0];
+        assert_eq!(first.story.attributes.title, "First Story");
+        assert_eq!(first.story.attributes.completion_status, Some(CompletionStatus::Complete));
+        assert_eq!(first.story.related_id("author"), Some("2"));
+        assert_eq!(first.story.related_ids("tags"), ["7"]);
+        assert_eq!(first.author.as_ref().unwrap().attributes.name, "Mock Author");
+        assert_eq!(first.chapters.iter().map(|c| c.id.as_str()).collect::<Vec<_>>(), ["31", "32"]);
+        assert_eq!(first.chapters[0].related_id("story"), Some("3"));
+        assert!(first.chapters[0].attributes.content.is_none());
+        assert_eq!(first.path.as_deref(), Some("epub/a/first-3.epub"));
+
+        let state = SyncState::from_fimfarchive(&stories);
+        assert_eq!(state.story_ids(), [3, 9]);
+        assert_eq!(state.stories[&3].chapters["31"].as_deref(), Some("2019-12-01T00:00:00+00:00"));
+    }
+
+    #[test]
+    fn loads_chapters_from_a_directory() {
+        let dir = std::env::temp_dir().join(format!("fimapi-fimfarchive-{}-dir", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("epub/a")).unwrap();
+        std::fs::write(dir.join("index.json"), INDEX).unwrap();
+        std::fs::write(dir.join("epub/a/first-3.epub"), epub()).unwrap();
+
+        let mut archive = Fimfarchive::open(&dir).unwrap();
+        let mut stories = archive.stories().unwrap();
+        archive.load_chapters(&mut stories[0]).unwrap();
+        let missing = archive.load_chapters(&mut stories[1]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let once = &stories[0].chapters[0].attributes;
+        assert_eq!(once.content_html.as_deref(), Some("<p><b>Once</b> upon a time.</p>"));
+        assert_eq!(once.content.as_deref(), Some("[b]Once[/b] upon a time."));
+        assert_eq!(stories[0].chapters[1].attributes.content.as_deref(), Some("The end."));
+        assert!(matches!(missing, Err(FimfarchiveError::Io { .. })));
+    }
+}
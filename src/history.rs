@@ -0,0 +1,354 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the [HistoryExporter], which exports everything a user has read as a portable
+//! [ReadingHistory], for backups or for moving to another tracker.
+//!
+//! The history combines the chapters the user marked as read with the stories on their
+//! bookshelves, and is filled in with the titles of stories and chapters. It can be saved as
+//! JSON, or as CSV with one row per chapter read:
+//!
+//! ```text
+//! story_id,story_title,author_id,url,status,bookshelves,chapter_id,chapter_number,chapter_title,date_read
+//! 1,The Mock Story,2,https://www.fimfiction.net/story/1,finished,Favourites,11,1,The Beginning,2020-06-01T12:00:00+00:00
+//! ```
+//!
+//! Reading history is private, so the client must be authorized as the user, with the
+//! [ReadChapterRead][crate::auth::scopes::Scope::ReadChapterRead] scope.
+
+use crate::archive::parse_id;
+use crate::bookshelf::{BookshelfError, BookshelfTransfer};
+use crate::client::Client;
+use crate::model::CompletionStatus;
+use crate::response::Error;
+use crate::sync::is_missing;
+use crate::util::write_csv_record;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The URL stories are linked at.
+pub const STORY_URL: &str = "https://www.fimfiction.net/story";
+
+/// The errors which can occur while exporting reading history.
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum HistoryError {
+    /// A request to FimFic failed.
+    #[error("request failed: {0}")]
+    Request(#[from] Error),
+    /// The bookshelves could not be exported.
+    #[error("could not export bookshelves: {0}")]
+    Bookshelves(#[from] BookshelfError),
+    /// The history could not be read from or written to JSON.
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A chapter the user has read.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadChapter {
+    /// The id of the chapter.
+    pub chapter_id: u64,
+    /// The position of the chapter in its story, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chapter_number: Option<u64>,
+    /// The title of the chapter, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// When the chapter was marked as read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_read: Option<String>,
+}
+
+/// A story the user has read some of, or keeps on a bookshelf.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadStory {
+    /// The id of the story.
+    pub story_id: u64,
+    /// The title of the story. Unknown if the story was deleted or details were not fetched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The id of the author, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author_id: Option<u64>,
+    /// The link to the story.
+    pub url: String,
+    /// The number of chapters the story has, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_chapters: Option<u64>,
+    /// The completion status of the story, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_status: Option<CompletionStatus>,
+    /// The names of the bookshelves the story is on.
+    #[serde(default)]
+    pub bookshelves: Vec<String>,
+    /// The chapters read, in story order.
+    #[serde(default)]
+    pub chapters: Vec<ReadChapter>,
+    /// When a chapter of the story was last marked as read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_read: Option<String>,
+}
+
+impl ReadStory {
+    fn new(story_id: u64) -> Self {
+        ReadStory {
+            story_id,
+            url: format!("{}/{}", STORY_URL, story_id),
+            ..Default::default()
+        }
+    }
+
+    /// Whether every chapter of the story was read. False if the number of chapters is not known.
+    pub fn is_finished(&self) -> bool {
+        self.num_chapters.is_some_and(|n| n > 0 && self.chapters.len() as u64 >= n)
+    }
+
+    /// A short reading status, as used by most trackers: `finished`, `reading`, or `shelved`
+    /// for stories which are only on a bookshelf.
+    pub fn status(&self) -> &'static str {
+        if self.chapters.is_empty() {
+            "shelved"
+        } else if self.is_finished() {
+            "finished"
+        } else {
+            "reading"
+        }
+    }
+}
+
+/// Everything a user has read, most recently read first.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadingHistory {
+    /// The id of the user.
+    pub user_id: u64,
+    /// The stories.
+    pub stories: Vec<ReadStory>,
+}
+
+const CSV_HEADER: [&str; 10] = [
+    "story_id", "story_title", "author_id", "url", "status", "bookshelves",
+    "chapter_id", "chapter_number", "chapter_title", "date_read",
+];
+
+impl ReadingHistory {
+    /// Writes the history as pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String, HistoryError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Reads a history written by [to_json][Self::to_json].
+    pub fn from_json(json: &str) -> Result<Self, HistoryError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Writes the history as CSV, with one row per chapter read. Stories with no chapters read
+    /// get a single row without a chapter. Bookshelves are separated by semicolons.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        write_csv_record(&mut out, &CSV_HEADER);
+        let text = |n: Option<u64>| n.map(|n| n.to_string()).unwrap_or_default();
+        for story in &self.stories {
+            let story_id = story.story_id.to_string();
+            let author_id = text(story.author_id);
+            let bookshelves = story.bookshelves.join("; ");
+            let story_fields = [
+                story_id.as_str(), story.title.as_deref().unwrap_or_default(), &author_id, &story.url,
+                story.status(), &bookshelves,
+            ];
+            if story.chapters.is_empty() {
+                let mut fields = story_fields.to_vec();
+                fields.extend_from_slice(&["", "", "", ""]);
+                write_csv_record(&mut out, &fields);
+            }
+            for chapter in &story.chapters {
+                let chapter_id = chapter.chapter_id.to_string();
+                let number = text(chapter.chapter_number);
+                let mut fields = story_fields.to_vec();
+                fields.extend_from_slice(&[
+                    &chapter_id, &number, chapter.title.as_deref().unwrap_or_default(),
+                    chapter.date_read.as_deref().unwrap_or_default(),
+                ]);
+                write_csv_record(&mut out, &fields);
+            }
+        }
+        out
+    }
+}
+
+/// Exports reading history.
+#[derive(Debug, Clone)]
+pub struct HistoryExporter {
+    include_bookshelves: bool,
+    include_details: bool,
+}
+
+impl Default for HistoryExporter {
+    fn default() -> Self {
+        HistoryExporter {
+            include_bookshelves: true,
+            include_details: true,
+        }
+    }
+}
+
+impl HistoryExporter {
+    /// Creates an exporter which includes bookshelves and fetches titles.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the stories on the user's bookshelves are included.
+    pub fn include_bookshelves(mut self, include_bookshelves: bool) -> Self {
+        self.include_bookshelves = include_bookshelves;
+        self
+    }
+
+    /// Sets whether every story and its chapter list are fetched to fill in titles, authors,
+    /// and chapter numbers. Without them, the history only holds ids and dates.
+    pub fn include_details(mut self, include_details: bool) -> Self {
+        self.include_details = include_details;
+        self
+    }
+
+    /// Exports the reading history of the user with the given id. Stories which were deleted
+    /// are kept, without their details.
+    pub async fn export(&self, client: &Client, user_id: u64) -> Result<ReadingHistory, HistoryError> {
+        let mut stories: BTreeMap<u64, ReadStory> = BTreeMap::new();
+        for read in client.chapter_reads(user_id).await? {
+            let story_id = match read.related_id("story") {
+                Some(id) => parse_id(id)?,
+                None => continue,
+            };
+            let chapter_id = match read.related_id("chapter") {
+                Some(id) => parse_id(id)?,
+                None => continue,
+            };
+            let story = stories.entry(story_id).or_insert_with(|| ReadStory::new(story_id));
+            story.chapters.push(ReadChapter {
+                chapter_id,
+                date_read: read.attributes.date_read,
+                ..Default::default()
+            });
+        }
+
+        if self.include_bookshelves {
+            let export = BookshelfTransfer::new().export(client, user_id).await?;
+            for shelf in export.bookshelves {
+                for id in shelf.story_ids {
+                    stories.entry(id).or_insert_with(|| ReadStory::new(id)).bookshelves.push(shelf.name.clone());
+                }
+            }
+        }
+
+        if self.include_details {
+            self.fill_details(client, &mut stories).await?;
+        }
+
+        let mut stories: Vec<ReadStory> = stories.into_values().map(|mut story| {
+            story.chapters.sort_by(|a, b| (a.chapter_number, &a.date_read).cmp(&(b.chapter_number, &b.date_read)));
+            story.last_read = story.chapters.iter().filter_map(|c| c.date_read.clone()).max();
+            story
+        }).collect();
+        // Most recent first; stories which were never read come last.
+        stories.sort_by(|a, b| b.last_read.cmp(&a.last_read));
+        Ok(ReadingHistory { user_id, stories })
+    }
+
+    async fn fill_details(&self, client: &Client, stories: &mut BTreeMap<u64, ReadStory>) -> Result<(), HistoryError> {
+        let ids: Vec<u64> = stories.keys().copied().collect();
+        for (id, result) in ids.iter().zip(client.batch_stories(ids.iter().copied()).await) {
+            let fetched = match result {
+                Ok(fetched) => fetched,
+                Err(e) if is_missing(&e) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let story = stories.get_mut(id).expect("every id is in the map");
+            story.author_id = fetched.related_id("author").map(parse_id).transpose()?;
+            story.title = Some(fetched.attributes.title);
+            story.num_chapters = Some(fetched.attributes.num_chapters);
+            story.completion_status = fetched.attributes.completion_status;
+        }
+
+        let read: Vec<u64> = stories.iter().filter(|(_, s)| !s.chapters.is_empty()).map(|(id, _)| *id).collect();
+        let listings = futures::future::join_all(read.iter().map(|&id| client.story_chapters(id))).await;
+        for (id, listing) in read.iter().zip(listings) {
+            let listing = match listing {
+                Ok(listing) => listing,
+                Err(e) if is_missing(&e) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let story = stories.get_mut(id).expect("every id is in the map");
+            for chapter in &mut story.chapters {
+                let listed = listing.iter().find(|c| parse_id(&c.id).ok() == Some(chapter.chapter_id));
+                if let Some(listed) = listed {
+                    chapter.chapter_number = Some(listed.attributes.chapter_number);
+                    chapter.title = Some(listed.attributes.title.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fixtures, MockClient};
+
+    fn mock() -> MockClient {
+        let mock = MockClient::new();
+        mock.on_get("/chapter-reads", fixtures::CHAPTER_READS)
+            .on_get("/bookshelves", fixtures::BOOKSHELVES)
+            .on_get("/bookshelves/21/items", fixtures::BOOKSHELF_ITEMS)
+            .on_get("/bookshelves/22/items", r#"{"data":[]}"#)
+            .on_get("/stories/1", fixtures::STORY)
+            .on_get("/stories/1/chapters", fixtures::CHAPTERS)
+            .on("GET", "/stories/3", 404, fixtures::NOT_FOUND)
+            .on("GET", "/stories/3/chapters", 404, fixtures::NOT_FOUND);
+        mock
+    }
+
+    #[tokio::test]
+    async fn exports_reads_and_bookshelves() {
+        let history = HistoryExporter::new().export(&mock().client(), 2).await.unwrap();
+
+        let ids: Vec<u64> = history.stories.iter().map(|s| s.story_id).collect();
+        assert_eq!(ids, vec![1, 3]);
+        let first = &history.stories[0];
+        assert_eq!(first.title.as_deref(), Some("The Mock Story"));
+        assert_eq!((first.author_id, first.status()), (Some(2), "finished"));
+        assert_eq!(first.bookshelves, vec!["Favourites".to_owned()]);
+        assert_eq!(first.last_read.as_deref(), Some("2020-06-03T12:00:00+00:00"));
+        assert_eq!(first.chapters[1], ReadChapter {
+            chapter_id: 12,
+            chapter_number: Some(2),
+            title: Some("The End".into()),
+            date_read: Some("2020-06-03T12:00:00+00:00".into()),
+        });
+
+        // Story 3 was deleted, but reading it is still part of the history.
+        let deleted = &history.stories[1];
+        assert_eq!((deleted.title.as_ref(), deleted.status()), (None, "reading"));
+        assert_eq!(deleted.chapters.len(), 1);
+
+        assert_eq!(ReadingHistory::from_json(&history.to_json().unwrap()).unwrap(), history);
+    }
+
+    #[tokio::test]
+    async fn exports_without_extras() {
+        let mock = mock();
+        let history = HistoryExporter::new()
+            .include_bookshelves(false)
+            .include_details(false)
+            .export(&mock.client(), 2)
+            .await
+            .unwrap();
+        assert_eq!(mock.requests().len(), 1);
+
+        let csv = history.to_csv();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some(CSV_HEADER.join(",").as_str()));
+        assert_eq!(lines.next(), Some("1,,,https://www.fimfiction.net/story/1,reading,,11,,,2020-06-01T12:00:00+00:00"));
+        assert_eq!(lines.count(), 2);
+    }
+}
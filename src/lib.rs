@@ -9,6 +9,25 @@ use std::str::FromStr;
 pub mod client;
 pub mod response;
 pub mod auth;
+pub mod model;
+pub mod options;
+pub mod retry;
+pub mod transport;
+pub mod archive;
+pub mod sync;
+pub mod watch;
+pub mod bbcode;
+pub mod publish;
+pub mod bookshelf;
+pub mod history;
+#[cfg(feature = "feed")]
+pub mod feed;
+#[cfg(feature = "epub")]
+pub mod epub;
+#[cfg(feature = "fimfarchive")]
+pub mod fimfarchive;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub(crate) mod util;
 #[cfg(test)]
 pub(crate) mod test;
@@ -0,0 +1,368 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the resource types returned by the FimFic API.
+//!
+//! FimFic speaks [JSON:API](https://jsonapi.org), so every resource is a [Resource] with an id,
+//! a type, some attributes, and relationships to other resources.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A top-level JSON:API document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document<T> {
+    /// The primary data of the document.
+    pub data: T,
+    /// Related resources included alongside the primary data.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub included: Vec<serde_json::Value>,
+    /// Links related to the primary data, e.g. pagination links.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<Links>,
+}
+
+/// Links attached to a [Document].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Links {
+    /// The URL of the next page of a collection, if there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+/// Identifies a resource without its attributes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ResourceId {
+    /// The id of the resource.
+    pub id: String,
+    /// The type of the resource, e.g. `story`.
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+/// The data of a relationship, which links to one or many resources.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RelationshipData {
+    /// A to-one relationship. May be empty.
+    One(Option<ResourceId>),
+    /// A to-many relationship.
+    Many(Vec<ResourceId>),
+}
+
+/// A relationship between a [Resource] and other resources.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Relationship {
+    /// The related resources.
+    pub data: RelationshipData,
+}
+
+/// A single JSON:API resource object.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resource<A> {
+    /// The id of the resource.
+    pub id: String,
+    /// The type of the resource, e.g. `story`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The attributes of the resource.
+    pub attributes: A,
+    /// The relationships of the resource, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub relationships: BTreeMap<String, Relationship>,
+}
+
+impl<A> Resource<A> {
+    /// Returns the ids of the resources related through the named relationship.
+    pub fn related_ids(&self, name: &str) -> Vec<&str> {
+        match self.relationships.get(name).map(|r| &r.data) {
+            Some(RelationshipData::One(Some(r))) => vec![r.id.as_str()],
+            Some(RelationshipData::Many(rs)) => rs.iter().map(|r| r.id.as_str()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the id of the single resource related through the named relationship.
+    pub fn related_id(&self, name: &str) -> Option<&str> {
+        self.related_ids(name).into_iter().next()
+    }
+}
+
+/// The completion status of a story.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompletionStatus {
+    /// The story is still being written.
+    Incomplete,
+    /// The story is finished.
+    Complete,
+    /// The story is on hiatus.
+    Hiatus,
+    /// The story was cancelled.
+    Cancelled,
+    /// A status this crate does not know about.
+    #[serde(other)]
+    Unknown,
+}
+
+/// The cover art of a story, in several sizes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CoverImage {
+    /// URL of the thumbnail.
+    pub thumbnail: Option<String>,
+    /// URL of the medium size image.
+    pub medium: Option<String>,
+    /// URL of the large size image.
+    pub large: Option<String>,
+    /// URL of the full size image.
+    pub full: Option<String>,
+}
+
+/// A display color.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Color {
+    /// The color as a hex string, without the leading `#`.
+    pub hex: String,
+    /// The color as RGB components.
+    #[serde(default)]
+    pub rgb: Vec<u8>,
+}
+
+/// The attributes of a story.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StoryAttributes {
+    /// The title of the story.
+    pub title: String,
+    /// The short description, in plain text.
+    #[serde(default)]
+    pub short_description: Option<String>,
+    /// The full description, in BBCode.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The full description, rendered to HTML.
+    #[serde(default)]
+    pub description_html: Option<String>,
+    /// When the story was last modified in any way.
+    #[serde(default)]
+    pub date_modified: Option<String>,
+    /// When a chapter was last added to the story.
+    #[serde(default)]
+    pub date_updated: Option<String>,
+    /// When the story was first published.
+    #[serde(default)]
+    pub date_published: Option<String>,
+    /// Whether the story is published.
+    #[serde(default)]
+    pub published: bool,
+    /// The completion status of the story.
+    #[serde(default)]
+    pub completion_status: Option<CompletionStatus>,
+    /// The content rating of the story, e.g. `everyone`.
+    #[serde(default)]
+    pub content_rating: Option<String>,
+    /// The cover art, if the story has any.
+    #[serde(default)]
+    pub cover_image: Option<CoverImage>,
+    /// The display color of the story.
+    #[serde(default)]
+    pub color: Option<Color>,
+    /// The number of chapters.
+    #[serde(default)]
+    pub num_chapters: u64,
+    /// The number of words.
+    #[serde(default)]
+    pub num_words: u64,
+    /// The number of views of the most viewed chapter.
+    #[serde(default)]
+    pub num_views: u64,
+    /// The total number of views across all chapters.
+    #[serde(default)]
+    pub total_num_views: u64,
+    /// The number of comments.
+    #[serde(default)]
+    pub num_comments: u64,
+    /// The number of likes.
+    #[serde(default)]
+    pub num_likes: i64,
+    /// The number of dislikes.
+    #[serde(default)]
+    pub num_dislikes: i64,
+}
+
+/// A story. The author is available through the `author` relationship and the tags through
+/// the `tags` relationship.
+pub type Story = Resource<StoryAttributes>;
+
+/// Changes to the attributes of a story, for creating or updating one.
+/// Only the attributes which are set are sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StoryChanges {
+    /// The title of the story.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The short description, in plain text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub short_description: Option<String>,
+    /// The full description, in BBCode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The content rating of the story, e.g. `everyone`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_rating: Option<String>,
+    /// The completion status of the story.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_status: Option<CompletionStatus>,
+    /// Whether the story is published.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<bool>,
+}
+
+/// The attributes of a chapter.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChapterAttributes {
+    /// The position of the chapter in its story, starting from 1.
+    pub chapter_number: u64,
+    /// The title of the chapter.
+    pub title: String,
+    /// Whether the chapter is published.
+    #[serde(default)]
+    pub published: bool,
+    /// The number of words.
+    #[serde(default)]
+    pub num_words: u64,
+    /// The number of views.
+    #[serde(default)]
+    pub num_views: u64,
+    /// When the chapter was first published.
+    #[serde(default)]
+    pub date_published: Option<String>,
+    /// When the chapter was last modified.
+    #[serde(default)]
+    pub date_modified: Option<String>,
+    /// The text of the chapter, in BBCode. Only present when a single chapter is fetched.
+    #[serde(default)]
+    pub content: Option<String>,
+    /// The text of the chapter, rendered to HTML. Only present when a single chapter is fetched.
+    #[serde(default)]
+    pub content_html: Option<String>,
+    /// The author's note, in BBCode.
+    #[serde(default)]
+    pub authors_note: Option<String>,
+}
+
+/// A chapter of a story. The story is available through the `story` relationship.
+pub type Chapter = Resource<ChapterAttributes>;
+
+/// Changes to the attributes of a chapter, for creating or updating one.
+/// Only the attributes which are set are sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ChapterChanges {
+    /// The title of the chapter.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The text of the chapter, in BBCode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// The author's note, in BBCode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authors_note: Option<String>,
+    /// Whether the chapter is published.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<bool>,
+}
+
+/// The attributes of a user.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserAttributes {
+    /// The name of the user.
+    pub name: String,
+    /// The bio of the user, in BBCode.
+    #[serde(default)]
+    pub bio: Option<String>,
+    /// The bio of the user, rendered to HTML.
+    #[serde(default)]
+    pub bio_html: Option<String>,
+    /// The avatar of the user, by size in pixels.
+    #[serde(default)]
+    pub avatar: BTreeMap<String, String>,
+    /// The display color of the user.
+    #[serde(default)]
+    pub color: Option<Color>,
+    /// The number of followers.
+    #[serde(default)]
+    pub num_followers: u64,
+    /// The number of published stories.
+    #[serde(default)]
+    pub num_stories: u64,
+    /// The number of blog posts.
+    #[serde(default)]
+    pub num_blog_posts: u64,
+    /// When the user joined.
+    #[serde(default)]
+    pub date_joined: Option<String>,
+}
+
+/// A user.
+pub type User = Resource<UserAttributes>;
+
+/// The attributes of a bookshelf.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookshelfAttributes {
+    /// The name of the bookshelf.
+    pub name: String,
+    /// The description, in plain text.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Who can see the bookshelf: `public`, `unlisted`, or `private`.
+    #[serde(default)]
+    pub privacy: Option<String>,
+    /// The display color of the bookshelf.
+    #[serde(default)]
+    pub color: Option<Color>,
+    /// The number of stories on the bookshelf.
+    #[serde(default)]
+    pub num_stories: u64,
+    /// The position of the bookshelf among its owner's bookshelves.
+    #[serde(default)]
+    pub order: Option<u64>,
+}
+
+/// A bookshelf. The owner is available through the `user` relationship.
+pub type Bookshelf = Resource<BookshelfAttributes>;
+
+/// Changes to the attributes of a bookshelf, for creating or updating one.
+/// Only the attributes which are set are sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BookshelfChanges {
+    /// The name of the bookshelf.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The description, in plain text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Who can see the bookshelf: `public`, `unlisted`, or `private`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub privacy: Option<String>,
+}
+
+/// The attributes of an item on a bookshelf.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookshelfItemAttributes {
+    /// When the story was added to the bookshelf.
+    #[serde(default)]
+    pub date_added: Option<String>,
+}
+
+/// A story on a bookshelf. The story is available through the `story` relationship.
+pub type BookshelfItem = Resource<BookshelfItemAttributes>;
+
+/// The attributes of a record of a chapter being read.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChapterReadAttributes {
+    /// When the chapter was marked as read.
+    #[serde(default)]
+    pub date_read: Option<String>,
+}
+
+/// A chapter a user has read. The chapter and its story are available through the `chapter`
+/// and `story` relationships.
+pub type ChapterRead = Resource<ChapterReadAttributes>;
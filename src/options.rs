@@ -0,0 +1,147 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains [RequestOptions], which bound how long requests made by a
+//! [Client][crate::client::Client] may take.
+//!
+//! ```no_run
+//! # async fn run(client: fimapi::client::Client) {
+//! use fimapi::options::{CancellationToken, RequestOptions};
+//! use std::time::Duration;
+//!
+//! let token = CancellationToken::new();
+//! let scoped = client.with_options(RequestOptions::new()
+//!     .timeout(Duration::from_secs(5))
+//!     .cancellation(token.clone()));
+//!
+//! // Somewhere else, e.g. when the user navigates away:
+//! token.cancel();
+//!
+//! // Returns Error::Cancelled as soon as the token is cancelled.
+//! let story = scoped.story(1).await;
+//! # }
+//! ```
+
+use futures::channel::oneshot;
+use futures::future::{FutureExt, Shared};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    sender: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+/// A handle which cancels every request it is attached to.
+///
+/// Clones share the same state, so any clone may cancel. Once cancelled, a token stays
+/// cancelled.
+#[derive(Clone)]
+pub struct CancellationToken {
+    state: Arc<CancellationState>,
+    receiver: Shared<oneshot::Receiver<()>>,
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        let (sender, receiver) = oneshot::channel();
+        CancellationToken {
+            state: Arc::new(CancellationState {
+                cancelled: AtomicBool::new(false),
+                sender: Mutex::new(Some(sender)),
+            }),
+            receiver: receiver.shared(),
+        }
+    }
+}
+
+impl CancellationToken {
+    /// Creates a token which has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every request using this token, now and in the future.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        // Dropping the sender wakes everyone waiting on the receiver.
+        self.state.sender.lock().unwrap().take();
+    }
+
+    /// Whether [cancel][Self::cancel] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Completes once the token is cancelled.
+    pub async fn cancelled(&self) {
+        if !self.is_cancelled() {
+            let _ = self.receiver.clone().await;
+        }
+    }
+}
+
+/// Limits applied to every request made through a client.
+/// Attach them with [Client::with_options][crate::client::Client::with_options].
+#[derive(Debug, Clone, Default)]
+pub struct RequestOptions {
+    deadline: Option<Instant>,
+    timeout: Option<Duration>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl RequestOptions {
+    /// Creates options without any limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails requests still running at the given instant with
+    /// [DeadlineExceeded][crate::response::Error::DeadlineExceeded].
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Fails each request which takes longer than the given duration, including any retries,
+    /// with [DeadlineExceeded][crate::response::Error::DeadlineExceeded].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fails requests with [Cancelled][crate::response::Error::Cancelled] once the token is
+    /// cancelled.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// The token requests are cancelled with, if any.
+    pub fn cancellation_token(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    /// The instant a request started at `start` must finish by, if it is limited at all.
+    pub(crate) fn deadline_from(&self, start: Instant) -> Option<Instant> {
+        let timeout = self.timeout.and_then(|t| start.checked_add(t));
+        match (self.deadline, timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Whether these options limit requests at all.
+    pub(crate) fn is_unlimited(&self) -> bool {
+        self.deadline.is_none() && self.timeout.is_none() && self.cancellation.is_none()
+    }
+}
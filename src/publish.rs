@@ -0,0 +1,653 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the [Publisher], which publishes a story from files on disk.
+//!
+//! A story is described by a [Manifest], a JSON file listing its metadata and its chapters in
+//! order. Chapter files are read as BBCode, Markdown, or HTML depending on their extension:
+//!
+//! ```json
+//! {
+//!     "title": "My Little Story",
+//!     "short_description": "Friendship is magic.",
+//!     "description": "A [b]long[/b] description.",
+//!     "content_rating": "everyone",
+//!     "completion_status": "incomplete",
+//!     "tags": [12, 34],
+//!     "cover": "cover.png",
+//!     "publish": true,
+//!     "chapters": [
+//!         { "title": "Prologue", "file": "chapters/prologue.md" },
+//!         { "title": "Chapter One", "file": "chapters/one.html", "authors_note": "Thanks for reading!" }
+//!     ]
+//! }
+//! ```
+//!
+//! What was uploaded is recorded in a state file next to the manifest, so publishing again
+//! resumes an interrupted run and only sends what changed since. Chapters are matched to the
+//! uploaded ones by their position; chapters removed from the manifest are left alone.
+//! Chapters and the story are only published once everything is uploaded.
+
+use crate::archive::parse_id;
+use crate::bbcode::import::{from_html, Warning};
+use crate::client::Client;
+use crate::model::{ChapterChanges, CompletionStatus, StoryChanges};
+use crate::response::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The errors which can occur while publishing a story.
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum PublishError {
+    /// A request to FimFic failed.
+    #[error("request failed: {0}")]
+    Request(#[from] Error),
+    /// Reading or writing a file failed.
+    #[error("could not access {}: {source}", path.display())]
+    Io {
+        /// The file which could not be read or written.
+        path: PathBuf,
+        /// The underlying error.
+        source: io::Error,
+    },
+    /// The manifest or the state file is not valid JSON of the expected shape.
+    #[error("could not parse {}: {source}", path.display())]
+    Parse {
+        /// The file which could not be parsed.
+        path: PathBuf,
+        /// The underlying error.
+        source: serde_json::Error,
+    },
+    /// A chapter is written in Markdown, but this crate was built without the `markdown` feature.
+    #[error("{} is Markdown, which needs the `markdown` feature", path.display())]
+    MarkdownDisabled {
+        /// The chapter file.
+        path: PathBuf,
+    },
+}
+
+/// The format of a chapter file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// FimFiction BBCode, uploaded as it is.
+    BBCode,
+    /// Markdown, converted with [crate::bbcode::markdown::to_bbcode].
+    Markdown,
+    /// HTML, converted with [crate::bbcode::import::from_html].
+    Html,
+}
+
+impl Format {
+    /// Guesses the format of a file from its extension, defaulting to BBCode.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+        match extension.as_str() {
+            "md" | "markdown" => Format::Markdown,
+            "html" | "htm" | "xhtml" => Format::Html,
+            _ => Format::BBCode,
+        }
+    }
+}
+
+/// A chapter listed in a [Manifest].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChapterEntry {
+    /// The title of the chapter.
+    pub title: String,
+    /// The file containing the text of the chapter.
+    pub file: PathBuf,
+    /// The format of the file. Guessed from its extension if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<Format>,
+    /// The author's note, in BBCode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authors_note: Option<String>,
+}
+
+/// A description of a story to publish: its metadata and its chapters, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The id of the story, if it already exists. A new story is created otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub story_id: Option<u64>,
+    /// The title of the story.
+    pub title: String,
+    /// The short description, in plain text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_description: Option<String>,
+    /// The full description, in BBCode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The content rating of the story, e.g. `everyone`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_rating: Option<String>,
+    /// The completion status of the story.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_status: Option<CompletionStatus>,
+    /// The ids of the story's tags. The tags are left alone if this is empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<u64>,
+    /// An image file to upload as the cover art.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cover: Option<PathBuf>,
+    /// Whether to publish the story and its chapters once they are uploaded.
+    #[serde(default)]
+    pub publish: bool,
+    /// The chapters of the story, in order.
+    #[serde(default)]
+    pub chapters: Vec<ChapterEntry>,
+    /// The file the manifest was loaded from.
+    #[serde(skip)]
+    source: Option<PathBuf>,
+}
+
+impl Manifest {
+    /// Loads a manifest from a JSON file. Relative paths in it are relative to the file.
+    pub async fn load(path: impl AsRef<Path>) -> Result<Self, PublishError> {
+        let path = path.as_ref();
+        let bytes = read(path).await?;
+        let mut manifest: Manifest = serde_json::from_slice(&bytes)
+            .map_err(|source| PublishError::Parse { path: path.to_owned(), source })?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for chapter in &mut manifest.chapters {
+            chapter.file = dir.join(&chapter.file);
+        }
+        manifest.cover = manifest.cover.map(|cover| dir.join(cover));
+        manifest.source = Some(path.to_owned());
+        Ok(manifest)
+    }
+
+    fn story_changes(&self) -> StoryChanges {
+        StoryChanges {
+            title: Some(self.title.clone()),
+            short_description: self.short_description.clone(),
+            description: self.description.clone(),
+            content_rating: self.content_rating.clone(),
+            completion_status: self.completion_status,
+            published: None,
+        }
+    }
+}
+
+/// A change made, or to be made in a dry run, while publishing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Step {
+    /// The story was created.
+    CreateStory,
+    /// The metadata of the story was updated.
+    UpdateStory,
+    /// The chapter at the given position, starting from 1, was uploaded as a new chapter.
+    CreateChapter(usize),
+    /// The chapter at the given position, starting from 1, was updated.
+    UpdateChapter(usize),
+    /// The tags of the story were set.
+    SetTags,
+    /// The cover art was uploaded.
+    UploadCover,
+    /// The chapter at the given position, starting from 1, was published.
+    PublishChapter(usize),
+    /// The story was published.
+    PublishStory,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::CreateStory => f.write_str("create the story"),
+            Step::UpdateStory => f.write_str("update the story"),
+            Step::CreateChapter(n) => write!(f, "create chapter {}", n),
+            Step::UpdateChapter(n) => write!(f, "update chapter {}", n),
+            Step::SetTags => f.write_str("set the tags"),
+            Step::UploadCover => f.write_str("upload the cover art"),
+            Step::PublishChapter(n) => write!(f, "publish chapter {}", n),
+            Step::PublishStory => f.write_str("publish the story"),
+        }
+    }
+}
+
+/// What happened while publishing a story.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PublishReport {
+    /// The id of the story. Only `None` in a dry run for a story which does not exist yet.
+    pub story_id: Option<u64>,
+    /// The changes made, in order. In a dry run, the changes which would have been made.
+    pub steps: Vec<Step>,
+    /// What was lost converting HTML chapters to BBCode, by chapter position.
+    pub warnings: Vec<(usize, Warning)>,
+}
+
+/// Publishes stories described by a [Manifest].
+#[derive(Debug, Clone, Default)]
+pub struct Publisher {
+    dry_run: bool,
+    state_file: Option<PathBuf>,
+}
+
+impl Publisher {
+    /// Creates a publisher which makes changes and keeps its state next to the manifest.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether to only report what would be changed, without changing anything.
+    /// Files are still read, so a dry run catches missing chapters and conversion problems.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Sets the file recording what was uploaded. Defaults to the manifest's file name with
+    /// the extension `state.json`. A manifest which was not loaded from a file has no state
+    /// file unless one is set, so every run starts from scratch.
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    /// Publishes the story described by the manifest, resuming from the state file if there is
+    /// one. The state file is updated after every change, so a failed run can be retried.
+    pub async fn publish(&self, client: &Client, manifest: &Manifest) -> Result<PublishReport, PublishError> {
+        let state_file = self.state_file.clone()
+            .or_else(|| manifest.source.as_ref().map(|p| p.with_extension("state.json")));
+        let state = match &state_file {
+            Some(path) => State::load(path).await?,
+            None => State::default(),
+        };
+        let mut run = Run { client, dry_run: self.dry_run, state_file, state, report: PublishReport::default() };
+        if run.state.story_id.is_none() {
+            run.state.story_id = manifest.story_id;
+        }
+
+        // Every file is read before the first change, so a missing one does not leave a
+        // half-published story behind.
+        let mut chapters = Vec::with_capacity(manifest.chapters.len());
+        for (i, entry) in manifest.chapters.iter().enumerate() {
+            chapters.push(run.read_chapter(i + 1, entry).await?);
+        }
+        let cover = match &manifest.cover {
+            Some(path) => Some((read(path).await?, media_type(path))),
+            None => None,
+        };
+
+        run.story(manifest).await?;
+        for (i, chapter) in chapters.iter().enumerate() {
+            run.chapter(i, chapter).await?;
+        }
+        if !manifest.tags.is_empty() {
+            run.tags(&manifest.tags).await?;
+        }
+        if let Some((image, media_type)) = cover {
+            run.cover(image, media_type).await?;
+        }
+        if manifest.publish {
+            run.publish(chapters.len()).await?;
+        }
+
+        run.report.story_id = run.state.story_id;
+        Ok(run.report)
+    }
+}
+
+/// What was uploaded, as saved in the state file. Digests identify the uploaded content, so
+/// that it is only uploaded again if it changes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct State {
+    story_id: Option<u64>,
+    #[serde(default)]
+    story: Option<String>,
+    #[serde(default)]
+    chapters: Vec<ChapterState>,
+    #[serde(default)]
+    tags: Option<String>,
+    #[serde(default)]
+    cover: Option<String>,
+    #[serde(default)]
+    published: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ChapterState {
+    id: u64,
+    digest: Option<String>,
+    published: bool,
+}
+
+impl State {
+    async fn load(path: &Path) -> Result<Self, PublishError> {
+        match tokio::fs::read(path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|source| PublishError::Parse { path: path.to_owned(), source }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+            Err(source) => Err(PublishError::Io { path: path.to_owned(), source }),
+        }
+    }
+}
+
+/// A single run of a [Publisher].
+struct Run<'a> {
+    client: &'a Client,
+    dry_run: bool,
+    state_file: Option<PathBuf>,
+    state: State,
+    report: PublishReport,
+}
+
+impl Run<'_> {
+    /// Records the step, returning whether it should be carried out.
+    fn step(&mut self, step: Step) -> bool {
+        self.report.steps.push(step);
+        !self.dry_run
+    }
+
+    fn story_id(&self) -> u64 {
+        self.state.story_id.expect("the story is created before anything else")
+    }
+
+    async fn save(&self) -> Result<(), PublishError> {
+        let path = match &self.state_file {
+            Some(path) if !self.dry_run => path,
+            _ => return Ok(()),
+        };
+        let json = serde_json::to_vec_pretty(&self.state).expect("the state is always serializable");
+        let tmp = path.with_extension("part");
+        tokio::fs::write(&tmp, json).await
+            .map_err(|source| PublishError::Io { path: tmp.clone(), source })?;
+        tokio::fs::rename(&tmp, path).await
+            .map_err(|source| PublishError::Io { path: path.to_owned(), source })
+    }
+
+    async fn read_chapter(&mut self, number: usize, entry: &ChapterEntry) -> Result<ChapterChanges, PublishError> {
+        let bytes = read(&entry.file).await?;
+        let text = String::from_utf8_lossy(&bytes);
+        let content = match entry.format.unwrap_or_else(|| Format::from_path(&entry.file)) {
+            Format::BBCode => text.into_owned(),
+            #[cfg(feature = "markdown")]
+            Format::Markdown => crate::bbcode::markdown::to_bbcode(&text),
+            #[cfg(not(feature = "markdown"))]
+            Format::Markdown => return Err(PublishError::MarkdownDisabled { path: entry.file.clone() }),
+            Format::Html => {
+                let import = from_html(&text);
+                self.report.warnings.extend(import.warnings.iter().map(|w| (number, w.clone())));
+                import.to_bbcode()
+            }
+        };
+        Ok(ChapterChanges {
+            title: Some(entry.title.clone()),
+            content: Some(content.trim_end().to_owned()),
+            authors_note: entry.authors_note.clone(),
+            published: None,
+        })
+    }
+
+    async fn story(&mut self, manifest: &Manifest) -> Result<(), PublishError> {
+        let changes = manifest.story_changes();
+        let digest = Some(digest(&serde_json::to_vec(&changes).expect("changes are always serializable")));
+
+        let id = match self.state.story_id {
+            Some(id) => id,
+            None => {
+                if self.step(Step::CreateStory) {
+                    let story = self.client.create_story(&changes).await?;
+                    self.state.story_id = Some(parse_id(&story.id)?);
+                    self.state.story = digest;
+                    self.state.published = story.attributes.published;
+                    self.save().await?;
+                }
+                return Ok(());
+            }
+        };
+
+        // Without a state file, the existing chapters are found by asking, so they are updated
+        // rather than uploaded again.
+        if self.state.chapters.is_empty() {
+            for chapter in self.client.story_chapters(id).await? {
+                self.state.chapters.push(ChapterState {
+                    id: parse_id(&chapter.id)?,
+                    digest: None,
+                    published: chapter.attributes.published,
+                });
+            }
+        }
+        if self.state.story != digest && self.step(Step::UpdateStory) {
+            self.client.update_story(id, &changes).await?;
+            self.state.story = digest;
+            self.save().await?;
+        }
+        Ok(())
+    }
+
+    async fn chapter(&mut self, index: usize, chapter: &ChapterChanges) -> Result<(), PublishError> {
+        let digest = Some(digest(&serde_json::to_vec(chapter).expect("changes are always serializable")));
+        match self.state.chapters.get(index).map(|c| (c.id, c.digest.clone())) {
+            Some((_, uploaded)) if uploaded == digest => {}
+            Some((id, _)) => {
+                if self.step(Step::UpdateChapter(index + 1)) {
+                    self.client.update_chapter(id, chapter).await?;
+                    self.state.chapters[index].digest = digest;
+                    self.save().await?;
+                }
+            }
+            None => {
+                if self.step(Step::CreateChapter(index + 1)) {
+                    let created = self.client.create_chapter(self.story_id(), chapter).await?;
+                    self.state.chapters.push(ChapterState {
+                        id: parse_id(&created.id)?,
+                        digest,
+                        published: created.attributes.published,
+                    });
+                    self.save().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn tags(&mut self, tags: &[u64]) -> Result<(), PublishError> {
+        let digest = Some(digest(&serde_json::to_vec(tags).expect("tags are always serializable")));
+        if self.state.tags != digest && self.step(Step::SetTags) {
+            self.client.set_story_tags(self.story_id(), tags).await?;
+            self.state.tags = digest;
+            self.save().await?;
+        }
+        Ok(())
+    }
+
+    async fn cover(&mut self, image: Vec<u8>, media_type: &str) -> Result<(), PublishError> {
+        let digest = Some(digest(&image));
+        if self.state.cover != digest && self.step(Step::UploadCover) {
+            self.client.upload_cover(self.story_id(), image, media_type).await?;
+            self.state.cover = digest;
+            self.save().await?;
+        }
+        Ok(())
+    }
+
+    async fn publish(&mut self, chapters: usize) -> Result<(), PublishError> {
+        let publish = ChapterChanges { published: Some(true), ..Default::default() };
+        for index in 0..chapters {
+            let state = self.state.chapters.get(index).map(|c| (c.id, c.published));
+            if state.is_some_and(|(_, published)| published) || !self.step(Step::PublishChapter(index + 1)) {
+                continue;
+            }
+            if let Some((id, _)) = state {
+                self.client.update_chapter(id, &publish).await?;
+                self.state.chapters[index].published = true;
+                self.save().await?;
+            }
+        }
+        if !self.state.published && self.step(Step::PublishStory) {
+            let story_id = self.story_id();
+            self.client.update_story(story_id, &StoryChanges { published: Some(true), ..Default::default() }).await?;
+            self.state.published = true;
+            self.save().await?;
+        }
+        Ok(())
+    }
+}
+
+async fn read(path: &Path) -> Result<Vec<u8>, PublishError> {
+    tokio::fs::read(path).await
+        .map_err(|source| PublishError::Io { path: path.to_owned(), source })
+}
+
+/// The media type of an image, from its extension.
+fn media_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A 64-bit FNV-1a hash of the bytes, which is stable across builds unlike the standard hasher.
+fn digest(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockClient;
+
+    const DRAFT: &str = r#"{"data":{"id":"1","type":"story","attributes":{"title":"The Mock Story"}}}"#;
+
+    fn draft_chapter(id: u64) -> String {
+        format!(r#"{{"data":{{"id":"{}","type":"chapter","attributes":{{"chapter_number":{},"title":"c"}}}}}}"#, id, id - 10)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fimapi-publish-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("story.json"), r#"{
+            "title": "The Mock Story",
+            "tags": [5, 7],
+            "cover": "cover.png",
+            "publish": true,
+            "chapters": [
+                { "title": "One", "file": "one.txt" },
+                { "title": "Two", "file": "two.html", "authors_note": "Thanks!" }
+            ]
+        }"#).unwrap();
+        std::fs::write(dir.join("one.txt"), "[b]Once[/b] upon a time.\n").unwrap();
+        std::fs::write(dir.join("two.html"), "<p>And they lived <i>happily</i> ever after.</p><script>x()</script>").unwrap();
+        std::fs::write(dir.join("cover.png"), "not really a png").unwrap();
+        dir
+    }
+
+    fn mock() -> MockClient {
+        let mock = MockClient::new();
+        mock.on("POST", "/stories", 201, DRAFT)
+            .on("PATCH", "/stories/1", 200, DRAFT)
+            .on("POST", "/stories/1/cover", 200, DRAFT)
+            .on("POST", "/stories/1/chapters", 201, draft_chapter(11))
+            .on("POST", "/stories/1/chapters", 201, draft_chapter(12))
+            .on("PATCH", "/chapters/11", 200, draft_chapter(11))
+            .on("PATCH", "/chapters/12", 200, draft_chapter(12));
+        mock
+    }
+
+    fn writes(mock: &MockClient) -> Vec<(String, String)> {
+        mock.requests().into_iter()
+            .filter(|r| r.method != "GET")
+            .map(|r| (r.method, r.path))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn publishes_a_new_story() {
+        let dir = temp_dir("new");
+        let mock = mock();
+        let manifest = Manifest::load(dir.join("story.json")).await.unwrap();
+        let report = Publisher::new().publish(&mock.client(), &manifest).await.unwrap();
+
+        assert_eq!(report.story_id, Some(1));
+        assert_eq!(report.steps, vec![
+            Step::CreateStory, Step::CreateChapter(1), Step::CreateChapter(2), Step::SetTags, Step::UploadCover,
+            Step::PublishChapter(1), Step::PublishChapter(2), Step::PublishStory,
+        ]);
+        assert_eq!(report.warnings, vec![(2, Warning::DroppedElement("script".into()))]);
+
+        let requests = mock.requests();
+        let second: serde_json::Value = serde_json::from_str(requests[2].body.as_deref().unwrap()).unwrap();
+        assert_eq!(second["data"]["attributes"], serde_json::json!({
+            "title": "Two", "content": "And they lived [i]happily[/i] ever after.", "authors_note": "Thanks!",
+        }));
+        let tags: serde_json::Value = serde_json::from_str(requests[3].body.as_deref().unwrap()).unwrap();
+        assert_eq!(tags["data"]["relationships"]["tags"]["data"][1], serde_json::json!({"id": "7", "type": "story_tag"}));
+        assert!(dir.join("story.state.json").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_and_only_sends_changes() {
+        let dir = temp_dir("resume");
+        let mock = mock();
+        let client = mock.client();
+        let manifest = Manifest::load(dir.join("story.json")).await.unwrap();
+        Publisher::new().publish(&client, &manifest).await.unwrap();
+        let before = writes(&mock).len();
+
+        let report = Publisher::new().publish(&client, &manifest).await.unwrap();
+        assert!(report.steps.is_empty());
+        assert_eq!(writes(&mock).len(), before);
+
+        std::fs::write(dir.join("one.txt"), "[b]Once[/b] upon a time, again.").unwrap();
+        let report = Publisher::new().publish(&client, &manifest).await.unwrap();
+        assert_eq!(report.steps, vec![Step::UpdateChapter(1)]);
+        assert_eq!(writes(&mock)[before..], [("PATCH".to_owned(), "/chapters/11".to_owned())]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dry_runs_change_nothing() {
+        let dir = temp_dir("dry");
+        let mock = mock();
+        let manifest = Manifest::load(dir.join("story.json")).await.unwrap();
+        let report = Publisher::new().dry_run(true).publish(&mock.client(), &manifest).await.unwrap();
+
+        assert_eq!(report.story_id, None);
+        assert_eq!(report.steps.len(), 8);
+        assert!(mock.requests().is_empty());
+        assert!(!dir.join("story.state.json").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn updates_existing_stories_without_state() {
+        let dir = temp_dir("existing");
+        let mock = mock();
+        mock.on_get("/stories/1/chapters", crate::testing::fixtures::CHAPTERS);
+        let mut manifest = Manifest::load(dir.join("story.json")).await.unwrap();
+        manifest.story_id = Some(1);
+        manifest.publish = false;
+        manifest.tags.clear();
+        manifest.cover = None;
+
+        let report = Publisher::new().dry_run(true).publish(&mock.client(), &manifest).await.unwrap();
+        assert_eq!(report.steps, vec![Step::UpdateStory, Step::UpdateChapter(1), Step::UpdateChapter(2)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn guesses_formats() {
+        assert_eq!(Format::from_path(Path::new("a/one.MD")), Format::Markdown);
+        assert_eq!(Format::from_path(Path::new("two.htm")), Format::Html);
+        assert_eq!(Format::from_path(Path::new("three.txt")), Format::BBCode);
+    }
+}
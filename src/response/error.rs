@@ -6,10 +6,15 @@ use std::convert::TryFrom;
 use std::ops::Rem;
 use std::borrow::Cow;
 use serde_json::Value;
+use std::time::Duration;
+use std::sync::Arc;
+use std::ops::Deref;
+use crate::retry::RateLimit;
 
 /// Ideally, you should never see one of these. These happen when an error code is unrecognized or
 /// malformed.
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum InvalidErrorCode<'value> {
     /// Unrecognized code
     #[error("Invalid error code: {0}")]
@@ -21,6 +26,7 @@ pub enum InvalidErrorCode<'value> {
 
 /// 400 errors
 #[derive(thiserror::Error, Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum Malformed {
     /// The body of the request was not valid. It should be valid JSON.
     #[error("The body of the request was not valid")]
@@ -28,9 +34,9 @@ pub enum Malformed {
     /// The requested included resource was not valid.
     #[error("The requested included resource was not valid.")]
     Include,
-    #[error("You should never see this.")]
-    #[doc(hidden)]
-    __Nonexhaustive,
+    /// An error code in this range which this crate does not recognize yet.
+    #[error("Unrecognized error code: {0}")]
+    Unknown(u64),
 }
 
 impl TryFrom<u64> for Malformed {
@@ -44,7 +50,7 @@ impl TryFrom<u64> for Malformed {
             match idx {
                 1 => Ok(Malformed::Body),
                 2 => Ok(Malformed::Include),
-                _ => Err(InvalidErrorCode::BadCode(value))
+                _ => Ok(Malformed::Unknown(value)),
             }
         }
     }
@@ -52,6 +58,7 @@ impl TryFrom<u64> for Malformed {
 
 /// 403 errors.
 #[derive(thiserror::Error, Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum Forbidden {
     /// Returned whenever you try to do something the authenticated user is not allowed to do.
     /// For example, trying to edit a story the user does not own will return this error.
@@ -65,9 +72,9 @@ pub enum Forbidden {
     /// Either check you have the data correct or request a new token via the auth flow.
     #[error("The token used to the request was not valid.")]
     InvalidToken,
-    #[error("You should never see this.")]
-    #[doc(hidden)]
-    __Nonexhaustive,
+    /// An error code in this range which this crate does not recognize yet.
+    #[error("Unrecognized error code: {0}")]
+    Unknown(u64),
 }
 
 impl TryFrom<u64> for Forbidden {
@@ -82,7 +89,7 @@ impl TryFrom<u64> for Forbidden {
                 0 => Ok(Forbidden::InvalidPermission),
                 1 => Ok(Forbidden::MissingScope),
                 2 => Ok(Forbidden::InvalidToken),
-                _ => Err(InvalidErrorCode::BadCode(value))
+                _ => Ok(Forbidden::Unknown(value)),
             }
         }
     }
@@ -90,6 +97,7 @@ impl TryFrom<u64> for Forbidden {
 
 /// 404 errors.
 #[derive(thiserror::Error, Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum NotFound {
     /// The requested resource was not found.
     /// Will return if the resource you're querying for a collection of does not exist either.
@@ -104,9 +112,9 @@ pub enum NotFound {
     /// Also check you are not trying to using string values for variables that expect numeric inputs.
     #[error("The requested endpoint does not exist.")]
     EndpointMissing,
-    #[error("You should never see this.")]
-    #[doc(hidden)]
-    __Nonexhaustive,
+    /// An error code in this range which this crate does not recognize yet.
+    #[error("Unrecognized error code: {0}")]
+    Unknown(u64),
 }
 
 impl TryFrom<u64> for NotFound {
@@ -121,7 +129,7 @@ impl TryFrom<u64> for NotFound {
                 0 => Ok(NotFound::ResourceNotFound),
                 1 => Ok(NotFound::InvalidApplication),
                 2 => Ok(NotFound::EndpointMissing),
-                _ => Err(InvalidErrorCode::BadCode(value))
+                _ => Ok(NotFound::Unknown(value)),
             }
         }
     }
@@ -129,6 +137,7 @@ impl TryFrom<u64> for NotFound {
 
 /// 422 errors.
 #[derive(thiserror::Error, Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum Unprocessable {
     /// A parameter required for the request was not present.
     #[error("A parameter required for the request was not present.")]
@@ -179,9 +188,9 @@ pub enum Unprocessable {
     /// Check the {json:api} documentation to see what format sorts should be provided in.
     #[error("The provided sort field was malformed.")]
     MalformedSortField,
-    #[error("You should never see this.")]
-    #[doc(hidden)]
-    __Nonexhaustive,
+    /// An error code in this range which this crate does not recognize yet.
+    #[error("Unrecognized error code: {0}")]
+    Unknown(u64),
 }
 
 impl TryFrom<u64> for Unprocessable {
@@ -211,7 +220,7 @@ impl TryFrom<u64> for Unprocessable {
             10 => InvalidAttribute,
             11 => InvalidSortField,
             12 => MalformedSortField,
-            _ => return Err(InvalidErrorCode::BadCode(value))
+            _ => Unknown(value),
         };
 
         Ok(o)
@@ -220,6 +229,7 @@ impl TryFrom<u64> for Unprocessable {
 
 /// The type of error received from FimFic.
 #[derive(thiserror::Error, Debug, Copy, Clone)]
+#[non_exhaustive]
 pub enum ErrorKind {
     /// 400 errors.
     #[error("{0}")]
@@ -233,37 +243,91 @@ pub enum ErrorKind {
     /// 422 errors.
     #[error("{0}")]
     Unprocessable(#[from] Unprocessable),
-    /// 429 errors.
+    /// 429 errors. Carries the rate limit information sent with the response, if there was any,
+    /// so callers who do not retry automatically can still schedule the next attempt.
     #[error("You are being rate limited.")]
-    RateLimited,
-    #[error("You should never see this.")]
-    #[doc(hidden)]
-    __Nonexhaustive,
+    RateLimited(Option<RateLimit>),
+    /// An error code which this crate does not recognize yet.
+    #[error("Unrecognized error code: {0}")]
+    Unknown(u64),
 }
 
-impl TryFrom<u64> for ErrorKind {
-    type Error = InvalidErrorCode<'static>;
-
-    fn try_from(value: u64) -> Result<Self, Self::Error> {
+impl From<u64> for ErrorKind {
+    /// Maps an error code to its kind. Codes this crate does not recognize map to an `Unknown`
+    /// variant, so new codes degrade gracefully.
+    fn from(value: u64) -> Self {
+        // The sub-kind conversions only fail for codes outside their range, which the match
+        // below already rules out.
         let v = value;
-        let o = match v / 10 {
-            400 => ErrorKind::Malformed(Malformed::try_from(v)?),
-            403 => ErrorKind::Forbidden(Forbidden::try_from(v)?),
-            404 => ErrorKind::NotFound(NotFound::try_from(v)?),
-            429 => ErrorKind::RateLimited,
-            v if v == 422 || v / 10 == 422 => ErrorKind::Unprocessable(Unprocessable::try_from(value)?),
-            v => return Err(InvalidErrorCode::BadCode(v))
+        let kind = match v / 10 {
+            400 => Malformed::try_from(v).map(ErrorKind::Malformed),
+            403 => Forbidden::try_from(v).map(ErrorKind::Forbidden),
+            404 => NotFound::try_from(v).map(ErrorKind::NotFound),
+            429 => Ok(ErrorKind::RateLimited(None)),
+            v if v == 422 || v / 10 == 422 => Unprocessable::try_from(value).map(ErrorKind::Unprocessable),
+            _ => Ok(ErrorKind::Unknown(value)),
         };
-        Ok(o)
+        kind.unwrap_or(ErrorKind::Unknown(value))
+    }
+}
+
+impl ErrorKind {
+    /// Whether the request may succeed if sent again later, i.e. it was rate limited.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ErrorKind::RateLimited(_))
+    }
+
+    /// How long to wait before trying again, if this is a rate limit and the server said.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ErrorKind::RateLimited(rl) => rl.as_ref().and_then(RateLimit::wait),
+            _ => None,
+        }
+    }
+
+    /// Whether the request failed because of the credentials, token, or scopes used.
+    /// [Forbidden::InvalidPermission] is not one: the user is authenticated, but not allowed
+    /// to do what was asked.
+    pub fn is_auth_error(&self) -> bool {
+        matches!(self,
+            ErrorKind::Forbidden(Forbidden::MissingScope)
+            | ErrorKind::Forbidden(Forbidden::InvalidToken)
+            | ErrorKind::NotFound(NotFound::InvalidApplication)
+            | ErrorKind::Unprocessable(Unprocessable::IncorrectSecret)
+            | ErrorKind::Unprocessable(Unprocessable::InvalidGrantType)
+            | ErrorKind::Unprocessable(Unprocessable::MissingAuthHeader)
+            | ErrorKind::Unprocessable(Unprocessable::MalformedAuthHeader))
+    }
+
+    /// Whether the request itself was wrong, and will fail the same way every time it is sent.
+    pub fn is_client_error(&self) -> bool {
+        !self.is_retryable() && !self.is_auth_error()
     }
 }
 
+/// Structured metadata for the kinds of [APIError] where FimFic documents what the metadata
+/// contains. See [APIError::typed_meta].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorMeta {
+    /// Attached to [Unprocessable::InvalidAttribute] and [Unprocessable::UnsupportedAttribute]
+    /// as `{"attribute": "title"}`: the name of the offending attribute.
+    Attribute(String),
+    /// The error kind has no documented metadata, or the metadata did not have the documented
+    /// shape. Holds the raw metadata.
+    Other(Value),
+}
+
 /// Represents an error received from FimFic.
 /// Contains the meta data necessary to understand what when wrong.
 #[derive(Debug, thiserror::Error, Clone)]
-#[error("Error from API: {kind}: {meta}")]
+#[error("Error from API ({code}): {kind}: {meta}")]
 pub struct APIError {
     kind: ErrorKind,
+    code: u64,
+    status: Option<reqwest::StatusCode>,
+    title: Option<String>,
+    detail: Option<String>,
     meta: serde_json::Value,
 }
 
@@ -273,35 +337,652 @@ impl APIError {
         self.kind
     }
 
+    /// Retrieves the error code exactly as FimFic sent it, even if it maps to an `Unknown` kind.
+    pub fn code(&self) -> u64 {
+        self.code
+    }
+
+    /// Retrieves the HTTP status of the response the error came from. Falls back to the status
+    /// in the error object itself when the error was not parsed from a response.
+    pub fn status(&self) -> Option<reqwest::StatusCode> {
+        self.status
+    }
+
+    /// Retrieves the short, human readable summary FimFic gave for the error, if any.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Retrieves the human readable explanation FimFic gave for the error, if any.
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_deref()
+    }
+
     /// Retrieves the metadata [Value][serde_json::Value] associated with the failure.
     pub fn meta(&self) -> &serde_json::Value {
         &self.meta
     }
+
+    /// Interprets the metadata according to the [ErrorKind]. Only metadata FimFic documents is
+    /// interpreted; anything else is returned as [ErrorMeta::Other].
+    pub fn typed_meta(&self) -> ErrorMeta {
+        use Unprocessable::*;
+        let attribute = match self.kind {
+            ErrorKind::Unprocessable(InvalidAttribute) | ErrorKind::Unprocessable(UnsupportedAttribute) => {
+                self.meta.get("attribute").and_then(Value::as_str)
+            }
+            _ => None,
+        };
+        match attribute {
+            Some(name) => ErrorMeta::Attribute(name.into()),
+            None => ErrorMeta::Other(self.meta.clone()),
+        }
+    }
+}
+
+/// Reads the status member of an error object, which FimFic sends as a string.
+fn parse_status(v: &Value) -> Option<reqwest::StatusCode> {
+    let status = v.as_str().and_then(|s| s.parse().ok()).or_else(|| v.as_u64())?;
+    reqwest::StatusCode::from_u16(u16::try_from(status).ok()?).ok()
+}
+
+/// A single error object as it appears in an error response.
+#[derive(Debug, serde::Deserialize)]
+struct RawError {
+    code: u64,
+    #[serde(default)]
+    status: Option<Value>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    detail: Option<String>,
+    #[serde(default)]
+    meta: Value,
+}
+
+impl From<RawError> for APIError {
+    fn from(raw: RawError) -> Self {
+        APIError {
+            kind: ErrorKind::from(raw.code),
+            code: raw.code,
+            status: raw.status.as_ref().and_then(parse_status),
+            title: raw.title,
+            detail: raw.detail,
+            meta: raw.meta,
+        }
+    }
+}
+
+/// The body of an error response. Deserializing straight into this avoids building and then
+/// copying out of a [Value] for the whole body.
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct ErrorDocument {
+    errors: Vec<RawError>,
+}
+
+impl ErrorDocument {
+    /// Converts the document into [APIErrors], or [None] if it contained no errors.
+    pub(crate) fn into_errors(self) -> Option<APIErrors> {
+        if self.errors.is_empty() {
+            None
+        } else {
+            Some(APIErrors::new(self.errors.into_iter().map(APIError::from).collect()))
+        }
+    }
 }
 
 impl TryFrom<serde_json::Value> for APIError {
     type Error = InvalidErrorCode<'static>;
 
     fn try_from(value: Value) -> Result<Self, Self::Error> {
-        let code = value.get("code")
-            .ok_or_else(|| InvalidErrorCode::Invalid(Cow::Owned(value.clone())))?
-            .as_u64()
-            .ok_or_else(|| InvalidErrorCode::Invalid(Cow::Owned(value.clone())))?;
-        let kind = ErrorKind::try_from(code)?;
-        let meta = value.get("meta").map(|x| x.clone()).unwrap_or_else(|| serde_json::Value::Null);
-        Ok(APIError { kind, meta })
+        let code = value.get("code").and_then(Value::as_u64);
+        match (code, value) {
+            (Some(code), Value::Object(mut map)) => Ok(APIError {
+                kind: ErrorKind::from(code),
+                code,
+                status: map.get("status").and_then(parse_status),
+                title: map.get("title").and_then(Value::as_str).map(String::from),
+                detail: map.get("detail").and_then(Value::as_str).map(String::from),
+                meta: map.remove("meta").unwrap_or(Value::Null),
+            }),
+            (_, value) => Err(InvalidErrorCode::Invalid(Cow::Owned(value))),
+        }
+    }
+}
+
+/// Every [APIError] FimFic returned for a single request, in the order they were returned.
+/// There is always at least one.
+#[derive(Debug, Clone)]
+pub struct APIErrors(Vec<APIError>);
+
+impl APIErrors {
+    /// Creates a collection from a non-empty list of errors.
+    pub(crate) fn new(errors: Vec<APIError>) -> Self {
+        debug_assert!(!errors.is_empty(), "APIErrors must contain at least one error");
+        APIErrors(errors)
+    }
+
+    /// Attaches rate limit information from the response to any rate limit errors.
+    pub(crate) fn with_rate_limit(mut self, rate_limit: Option<RateLimit>) -> Self {
+        for e in &mut self.0 {
+            if let ErrorKind::RateLimited(rl) = &mut e.kind {
+                *rl = rate_limit;
+            }
+        }
+        self
+    }
+
+    /// Records the HTTP status of the response on every error.
+    pub(crate) fn with_status(mut self, status: reqwest::StatusCode) -> Self {
+        for e in &mut self.0 {
+            e.status = Some(status);
+        }
+        self
+    }
+
+    /// How long to wait before trying again, if any of the errors is a rate limit and the
+    /// server said.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.iter().find_map(|e| e.kind().retry_after())
+    }
+
+    /// The first error returned.
+    pub fn first(&self) -> &APIError {
+        &self.0[0]
+    }
+
+    /// Iterates over every error returned.
+    pub fn iter(&self) -> std::slice::Iter<'_, APIError> {
+        self.0.iter()
+    }
+
+    /// The number of errors returned.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Always false, as FimFic never fails a request without saying why.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Consumes the collection, returning the errors.
+    pub fn into_vec(self) -> Vec<APIError> {
+        self.0
+    }
+}
+
+impl std::fmt::Display for APIErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for APIErrors {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.first())
+    }
+}
+
+impl From<APIError> for APIErrors {
+    fn from(e: APIError) -> Self {
+        APIErrors(vec![e])
+    }
+}
+
+impl<'a> IntoIterator for &'a APIErrors {
+    type Item = &'a APIError;
+    type IntoIter = std::slice::Iter<'a, APIError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for APIErrors {
+    type Item = APIError;
+    type IntoIter = std::vec::IntoIter<APIError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// Where an [Error] was created. With the `backtrace` feature, a backtrace is captured and the
+/// trace appears in the error's [source][std::error::Error::source] chain, just before the
+/// error's own source. Without it, the trace is always empty, so [Error] has the same shape
+/// whether or not the feature is enabled.
+#[derive(Debug, Clone, Default)]
+pub struct Trace(
+    #[cfg_attr(not(feature = "backtrace"), allow(dead_code))]
+    Option<Arc<std::backtrace::Backtrace>>,
+);
+
+impl Trace {
+    /// Captures a backtrace of the current location if the `backtrace` feature is enabled, and
+    /// returns an empty trace otherwise.
+    pub fn capture() -> Trace {
+        #[cfg(feature = "backtrace")]
+        let backtrace = Some(Arc::new(std::backtrace::Backtrace::force_capture()));
+        #[cfg(not(feature = "backtrace"))]
+        let backtrace = None;
+        Trace(backtrace)
+    }
+
+    /// The captured backtrace, if there is one.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> Option<&std::backtrace::Backtrace> {
+        self.0.as_deref()
+    }
+
+    /// This trace as a link of a source chain, if a backtrace was captured.
+    fn as_source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.as_ref().map(|_| self as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl std::fmt::Display for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(backtrace) => write!(f, "Error created at:\n{}", backtrace),
+            None => write!(f, "No backtrace was captured."),
+        }
+    }
+}
+
+impl std::error::Error for Trace {}
+
+mod sealed {
+    /// The sources an [Error][super::Error] can have.
+    pub trait Source: std::fmt::Debug {
+        fn as_error(&self) -> &(dyn std::error::Error + 'static);
+    }
+
+    impl Source for reqwest::Error {
+        fn as_error(&self) -> &(dyn std::error::Error + 'static) {
+            self
+        }
+    }
+
+    impl Source for serde_json::Error {
+        fn as_error(&self) -> &(dyn std::error::Error + 'static) {
+            self
+        }
+    }
+
+    impl Source for super::APIErrors {
+        fn as_error(&self) -> &(dyn std::error::Error + 'static) {
+            self
+        }
+    }
+
+    impl Source for Box<dyn std::error::Error + Send + Sync> {
+        fn as_error(&self) -> &(dyn std::error::Error + 'static) {
+            &**self
+        }
+    }
+}
+
+/// The source of an [Error], along with the [Trace] of where the error was created.
+/// Dereferences to the source.
+#[derive(Debug)]
+pub struct Traced<E> {
+    error: E,
+    trace: Trace,
+}
+
+impl<E> Traced<E> {
+    /// Wraps the source, capturing a [Trace] of the current location.
+    pub fn new(error: E) -> Self {
+        Traced { error, trace: Trace::capture() }
+    }
+
+    /// Where the error was created.
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    /// Consumes the wrapper, returning the source.
+    pub fn into_inner(self) -> E {
+        self.error
+    }
+}
+
+impl<E: sealed::Source + 'static> Traced<E> {
+    /// The trace, followed by the source, if a backtrace was captured. Otherwise just the
+    /// source, so that the source chain is the same as without the `backtrace` feature.
+    fn as_source(&self) -> &(dyn std::error::Error + 'static) {
+        match self.trace.0 {
+            Some(_) => self,
+            None => self.error.as_error(),
+        }
+    }
+}
+
+impl<E> Deref for Traced<E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        &self.error
+    }
+}
+
+impl<E> From<E> for Traced<E> {
+    fn from(error: E) -> Self {
+        Traced::new(error)
+    }
+}
+
+impl<E> std::fmt::Display for Traced<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.trace.fmt(f)
+    }
+}
+
+impl<E: sealed::Source + 'static> std::error::Error for Traced<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_error())
     }
 }
 
 /// Wrapper around the errors you may see while using this crate.
 /// This will typically be either HTTP errors or FimFic API errors.
-#[derive(thiserror::Error, Debug)]
+///
+/// Every error records the [Trace] of where it was created, which is only captured with the
+/// `backtrace` feature.
+#[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Wrapper around [reqwest] errors.
-    #[error("Error occurred while processing request: {0}")]
-    Request(#[from] reqwest::Error),
-    /// Wrapper around [APIError]
-    #[error("")]
-    API(#[from] APIError),
+    Request(Traced<reqwest::Error>),
+    /// Wrapper around the [APIErrors] returned for a request.
+    API(Traced<APIErrors>),
+    /// The response was not valid JSON, or did not have the expected shape.
+    /// This usually means the API has changed.
+    Deserialization {
+        /// The status code of the response.
+        status: reqwest::StatusCode,
+        /// The start of the response body.
+        body: String,
+        /// What went wrong while deserializing.
+        source: Traced<serde_json::Error>,
+    },
+    /// The response was valid JSON, but not what FimFic is documented to send, e.g. an error
+    /// response without any recognizable errors.
+    UnexpectedResponse {
+        /// The status code of the response.
+        status: reqwest::StatusCode,
+        /// What was wrong with the response.
+        reason: String,
+        /// Where the error was created.
+        trace: Trace,
+    },
+    /// FimFic failed to handle the request, i.e. responded with a 5xx status.
+    Server {
+        /// The status code of the response.
+        status: reqwest::StatusCode,
+        /// The start of the response body.
+        body: String,
+        /// Where the error was created.
+        trace: Trace,
+    },
+    /// FimFic is down for maintenance. This is returned for any 503 response, and for server
+    /// errors which show the maintenance page.
+    Maintenance {
+        /// How long the server asked us to wait before trying again, if it said.
+        retry_after: Option<Duration>,
+        /// Where the error was created.
+        trace: Trace,
+    },
+    /// The request was cancelled through its [CancellationToken][crate::options::CancellationToken].
+    Cancelled(Trace),
+    /// The request did not finish before its [deadline][crate::options::RequestOptions::deadline].
+    DeadlineExceeded(Trace),
+    /// An error raised by a custom [Transport][crate::transport::Transport].
+    Transport(Traced<Box<dyn std::error::Error + Send + Sync>>),
+    /// The [ClientBuilder][crate::client::ClientBuilder] was given options which can not be
+    /// used together, or which are out of range.
+    InvalidConfiguration {
+        /// What was wrong with the options.
+        reason: String,
+        /// Where the error was created.
+        trace: Trace,
+    },
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Request(e) => write!(f, "Error occurred while processing request: {}", **e),
+            Error::API(errors) => write!(f, "{}", **errors),
+            Error::Deserialization { status, source, .. } => {
+                write!(f, "Could not deserialize response with status {}: {}", status, **source)
+            }
+            Error::UnexpectedResponse { status, reason, .. } => {
+                write!(f, "Unexpected response with status {}: {}", status, reason)
+            }
+            Error::Server { status, body, .. } => write!(f, "Server error with status {}: {}", status, body),
+            Error::Maintenance { .. } => write!(f, "FimFic is down for maintenance."),
+            Error::Cancelled(_) => write!(f, "The request was cancelled."),
+            Error::DeadlineExceeded(_) => write!(f, "The request did not finish before its deadline."),
+            Error::Transport(e) => write!(f, "Error occurred in transport: {}", **e),
+            Error::InvalidConfiguration { reason, .. } => write!(f, "Invalid client configuration: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Request(e) => Some(e.as_source()),
+            Error::API(errors) => Some(errors.as_source()),
+            Error::Deserialization { source, .. } => Some(source.as_source()),
+            Error::Transport(e) => Some(e.as_source()),
+            _ => self.trace().as_source(),
+        }
+    }
 }
 
+impl Error {
+    /// Wraps an error raised by a custom [Transport][crate::transport::Transport].
+    pub fn transport<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> Error {
+        Error::Transport(Traced::new(error.into()))
+    }
+
+    /// Where this error was created. Empty unless the `backtrace` feature is enabled.
+    pub fn trace(&self) -> &Trace {
+        match self {
+            Error::Request(e) => e.trace(),
+            Error::API(errors) => errors.trace(),
+            Error::Deserialization { source, .. } => source.trace(),
+            Error::UnexpectedResponse { trace, .. }
+            | Error::Server { trace, .. }
+            | Error::Maintenance { trace, .. }
+            | Error::InvalidConfiguration { trace, .. } => trace,
+            Error::Cancelled(trace) | Error::DeadlineExceeded(trace) => trace,
+            Error::Transport(e) => e.trace(),
+        }
+    }
+
+    /// The backtrace of where this error was created, if it was captured.
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> Option<&std::backtrace::Backtrace> {
+        self.trace().backtrace()
+    }
+
+    /// How long the server asked us to wait before trying again, for rate limits and
+    /// maintenance downtime.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::API(errors) => errors.retry_after(),
+            Error::Maintenance { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Whether the failure is transient: rate limiting, maintenance, server errors, timeouts,
+    /// and connection failures. Retrying later may succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Request(e) => e.is_timeout() || e.is_connect()
+                || e.status().is_some_and(|s| s.is_server_error()),
+            Error::API(errors) => errors.iter().any(|e| e.kind().is_retryable()),
+            Error::Server { .. } | Error::Maintenance { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the request failed because of the credentials, token, or scopes used.
+    /// Retrying will not help until they are fixed.
+    pub fn is_auth_error(&self) -> bool {
+        match self {
+            Error::API(errors) => errors.iter().any(|e| e.kind().is_auth_error()),
+            Error::Request(e) => e.status() == Some(reqwest::StatusCode::UNAUTHORIZED),
+            _ => false,
+        }
+    }
+
+    /// Whether FimFic rejected the request itself, e.g. because a resource does not exist or an
+    /// attribute was invalid. The request will fail the same way every time it is sent.
+    pub fn is_client_error(&self) -> bool {
+        match self {
+            Error::API(_) => !self.is_retryable() && !self.is_auth_error(),
+            Error::Request(e) => e.is_builder()
+                || (e.status().is_some_and(|s| s.is_client_error()) && !self.is_auth_error()),
+            _ => false,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Request(Traced::new(e))
+    }
+}
+
+impl From<APIErrors> for Error {
+    fn from(errors: APIErrors) -> Self {
+        Error::API(Traced::new(errors))
+    }
+}
+
+impl From<APIError> for Error {
+    fn from(e: APIError) -> Self {
+        APIErrors::from(e).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn error(code: u64, meta: Value) -> APIError {
+        APIError::try_from(json!({ "code": code, "meta": meta })).unwrap()
+    }
+
+    #[test]
+    fn typed_meta_for_documented_kinds() {
+        let e = error(42210, json!({ "attribute": "title" }));
+        assert_eq!(e.typed_meta(), ErrorMeta::Attribute("title".into()));
+
+        let e = error(4226, json!({ "attribute": "colour" }));
+        assert_eq!(e.typed_meta(), ErrorMeta::Attribute("colour".into()));
+    }
+
+    #[test]
+    fn classifies_errors() {
+        let rate_limited: Error = error(4290, Value::Null).into();
+        assert!(rate_limited.is_retryable());
+        assert!(!rate_limited.is_auth_error() && !rate_limited.is_client_error());
+
+        let bad_token: Error = error(4032, Value::Null).into();
+        assert!(bad_token.is_auth_error());
+        assert!(!bad_token.is_retryable() && !bad_token.is_client_error());
+
+        let bad_secret: Error = error(4222, Value::Null).into();
+        assert!(bad_secret.is_auth_error());
+
+        let not_allowed: Error = error(4030, Value::Null).into();
+        assert!(not_allowed.is_client_error());
+        assert!(!not_allowed.is_auth_error());
+
+        let not_found: Error = error(4040, Value::Null).into();
+        assert!(not_found.is_client_error());
+        assert!(!not_found.is_retryable() && !not_found.is_auth_error());
+
+        assert!(Error::Maintenance { retry_after: None, trace: Trace::default() }.is_retryable());
+        let cancelled = Error::Cancelled(Trace::default());
+        assert!(!cancelled.is_retryable() && !cancelled.is_client_error());
+    }
+
+    #[test]
+    fn unknown_codes_degrade_gracefully() {
+        assert!(matches!(ErrorKind::from(4009), ErrorKind::Malformed(Malformed::Unknown(4009))));
+        assert!(matches!(ErrorKind::from(4039), ErrorKind::Forbidden(Forbidden::Unknown(4039))));
+        assert!(matches!(ErrorKind::from(4049), ErrorKind::NotFound(NotFound::Unknown(4049))));
+        assert!(matches!(ErrorKind::from(42299), ErrorKind::Unprocessable(Unprocessable::Unknown(42299))));
+        assert!(matches!(ErrorKind::from(4999), ErrorKind::Unknown(4999)));
+        assert!(matches!(ErrorKind::from(4290), ErrorKind::RateLimited(None)));
+        assert!(Malformed::try_from(4040).is_err());
+    }
+
+    #[test]
+    fn keeps_raw_code_and_status() {
+        let e = APIError::try_from(json!({ "status": "404", "code": 4049 })).unwrap();
+        assert_eq!(e.code(), 4049);
+        assert_eq!(e.status(), Some(reqwest::StatusCode::NOT_FOUND));
+        assert!(matches!(e.kind(), ErrorKind::NotFound(NotFound::Unknown(4049))));
+
+        let errors = APIErrors::from(e).with_status(reqwest::StatusCode::GONE);
+        assert_eq!(errors.first().status(), Some(reqwest::StatusCode::GONE));
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[test]
+    fn every_error_carries_a_trace() {
+        use std::error::Error as _;
+
+        let err: Error = error(4290, Value::Null).into();
+        assert!(err.backtrace().is_some());
+        assert!(err.is_retryable());
+        let trace = err.source().unwrap();
+        assert!(trace.to_string().starts_with("Error created at:"));
+        assert!(trace.source().unwrap().to_string().contains("rate limited"));
+
+        let err = Error::DeadlineExceeded(Trace::capture());
+        assert!(err.backtrace().is_some());
+        assert!(err.source().unwrap().to_string().starts_with("Error created at:"));
+
+        let err = Error::transport("connection reset");
+        assert!(err.backtrace().is_some());
+        let trace = err.source().unwrap();
+        assert_eq!(trace.source().unwrap().to_string(), "connection reset");
+    }
+
+    #[cfg(not(feature = "backtrace"))]
+    #[test]
+    fn traces_are_empty_without_the_feature() {
+        use std::error::Error as _;
+
+        let err: Error = error(4290, Value::Null).into();
+        assert!(err.source().unwrap().to_string().contains("rate limited"));
+        assert!(Error::DeadlineExceeded(Trace::capture()).source().is_none());
+    }
+
+    #[test]
+    fn typed_meta_falls_back_to_other() {
+        assert_eq!(error(4040, json!({ "attribute": "title" })).typed_meta(), ErrorMeta::Other(json!({ "attribute": "title" })));
+        assert_eq!(error(42210, json!({ "unexpected": 1 })).typed_meta(), ErrorMeta::Other(json!({ "unexpected": 1 })));
+
+        // FimFic does not document the metadata of the other kinds.
+        let meta = json!({ "supported_grant_types": ["client_credentials"] });
+        assert_eq!(error(4223, meta.clone()).typed_meta(), ErrorMeta::Other(meta));
+    }
+}
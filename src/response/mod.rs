@@ -4,36 +4,244 @@
 
 
 pub mod error;
-
-use crate::response::error::{InvalidErrorCode};
-use std::borrow::Cow;
+pub mod validation;
 
 pub use error::APIError;
+pub use error::APIErrors;
 pub use error::Error;
-use serde_json::Value;
-use std::convert::TryFrom;
+use crate::response::error::{ErrorDocument, Trace};
+use reqwest::StatusCode;
+use reqwest::header::CONTENT_TYPE;
+use crate::retry::RateLimit;
 
-pub(crate) trait ExtractErrExt {
-    fn extract_error(&self) -> Result<APIError, InvalidErrorCode<'_>>;
-}
+/// Text in the title of the FimFic maintenance page.
+const MAINTENANCE_MARKER: &str = "maintenance";
 
-impl ExtractErrExt for serde_json::Value {
-    fn extract_error(&self) -> Result<APIError, InvalidErrorCode<'_>> {
-        self.get("errors")
-            .and_then(|v| v.get(0))
-            .ok_or_else(|| InvalidErrorCode::Invalid(Cow::Borrowed(self)))
-            .and_then(|v| APIError::try_from(v.clone()))
-    }
+fn is_html(s: &reqwest::Response) -> bool {
+    s.headers().get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"))
 }
 
 pub(crate) async fn extract_api_response<T: serde::de::DeserializeOwned>(s: reqwest::Response) -> Result<T, Error> {
+    let status = s.status();
+    if status == StatusCode::SERVICE_UNAVAILABLE || (is_html(&s) && (status.is_client_error() || status.is_server_error())) {
+        let retry_after = RateLimit::from_headers(s.headers()).and_then(|r| r.retry_after);
+        let status_err = s.error_for_status_ref().err();
+        let body = s.bytes().await?;
+        let maintenance = status == StatusCode::SERVICE_UNAVAILABLE
+            || (status.is_server_error() && is_maintenance_page(&body));
+        return Err(match status_err {
+            _ if maintenance => Error::Maintenance { retry_after, trace: Trace::capture() },
+            _ if status.is_server_error() => Error::Server { status, body: body_snippet(&body), trace: Trace::capture() },
+            Some(e) => e.into(),
+            None => Error::UnexpectedResponse {
+                status,
+                reason: "HTML error page was not an error".into(),
+                trace: Trace::capture(),
+            },
+        });
+    }
+
     if s.status().is_client_error() {
-        let v = s.json::<Value>().await?;
-        Err(v.extract_error().unwrap())?
+        let rate_limit = RateLimit::from_headers(s.headers());
+        let body = s.bytes().await?;
+        let doc = match serde_json::from_slice::<ErrorDocument>(&body) {
+            Ok(doc) => doc,
+            Err(source) => {
+                return Err(Error::Deserialization { status, body: body_snippet(&body), source: source.into() })
+            }
+        };
+        match doc.into_errors() {
+            Some(errors) => Err(errors.with_status(status).with_rate_limit(rate_limit).into()),
+            None => Err(Error::UnexpectedResponse {
+                status,
+                reason: "error response contained no errors".into(),
+                trace: Trace::capture(),
+            }),
+        }
     } else if s.status().is_server_error() {
-        Err(s.error_for_status().unwrap_err())?
+        Err(Error::Server { status, body: body_snippet(&s.bytes().await?), trace: Trace::capture() })
     } else {
-        let o = s.json::<T>().await?;
-        Ok(o)
+        decode(status, &s.bytes().await?)
+    }
+}
+
+/// Whether an HTML error page is the FimFic maintenance page, which says so in its title. Other
+/// error pages may mention maintenance elsewhere, e.g. in a footer link or a story title.
+fn is_maintenance_page(body: &[u8]) -> bool {
+    let page = String::from_utf8_lossy(body).to_ascii_lowercase();
+    let title = page.find("<title").and_then(|start| {
+        let rest = &page[start..];
+        rest.get(rest.find('>')? + 1..rest.find("</title>")?)
+    });
+    title.is_some_and(|t| t.contains(MAINTENANCE_MARKER))
+}
+
+/// Extracts the raw body of a response for a file outside the API, such as a cover image.
+pub(crate) async fn extract_bytes(s: reqwest::Response) -> Result<Vec<u8>, Error> {
+    let status = s.status();
+    if status == StatusCode::SERVICE_UNAVAILABLE {
+        let retry_after = RateLimit::from_headers(s.headers()).and_then(|r| r.retry_after);
+        return Err(Error::Maintenance { retry_after, trace: Trace::capture() });
+    }
+    if status.is_server_error() {
+        return Err(Error::Server { status, body: body_snippet(&s.bytes().await?), trace: Trace::capture() });
+    }
+    let s = s.error_for_status()?;
+    Ok(s.bytes().await?.to_vec())
+}
+
+/// The most bytes of a body kept in an error for debugging.
+const MAX_BODY_SNIPPET: usize = 1024;
+
+/// Returns the start of a body as text, for including in errors.
+pub(crate) fn body_snippet(body: &[u8]) -> String {
+    let end = body.len().min(MAX_BODY_SNIPPET);
+    let mut snippet = String::from_utf8_lossy(&body[..end]).into_owned();
+    if end < body.len() {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+fn decode<T: serde::de::DeserializeOwned>(status: StatusCode, body: &[u8]) -> Result<T, Error> {
+    serde_json::from_slice(body).map_err(|source| Error::Deserialization {
+        status,
+        body: body_snippet(body),
+        source: source.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::build_response;
+    use serde_json::Value;
+
+    #[tokio::test]
+    async fn detects_maintenance() {
+        let headers = [("Retry-After".to_owned(), "120".to_owned())];
+        let res = build_response(503, &headers, b"Service Unavailable".to_vec()).unwrap();
+        match extract_api_response::<Value>(res).await {
+            Err(Error::Maintenance { retry_after, .. }) => assert_eq!(retry_after, Some(std::time::Duration::from_secs(120))),
+            other => panic!("expected maintenance, got {:?}", other),
+        }
+
+        let headers = [("Content-Type".to_owned(), "text/html; charset=utf-8".to_owned())];
+        let page = b"<html><head><title>Down for Maintenance</title></head><body>Back soon.</body></html>".to_vec();
+        let res = build_response(502, &headers, page).unwrap();
+        assert!(matches!(extract_api_response::<Value>(res).await, Err(Error::Maintenance { retry_after: None, .. })));
+    }
+
+    #[tokio::test]
+    async fn error_pages_mentioning_maintenance_are_not_maintenance() {
+        let headers = [("Content-Type".to_owned(), "text/html; charset=utf-8".to_owned())];
+        let page = b"<html><head><title>Not Found</title></head><body>Try \"Maintenance Day\" instead.</body></html>";
+        let res = build_response(404, &headers, page.to_vec()).unwrap();
+        assert!(matches!(extract_api_response::<Value>(res).await, Err(Error::Request(_))));
+
+        let page = b"<html><head><title>Error</title></head><body><a href=\"/maintenance\">Status</a></body></html>";
+        let res = build_response(500, &headers, page.to_vec()).unwrap();
+        assert!(matches!(extract_api_response::<Value>(res).await, Err(Error::Server { .. })));
+    }
+
+    #[tokio::test]
+    async fn extracts_every_error() {
+        let body = br#"{
+            "errors": [
+                { "code": 42210, "meta": { "attribute": "title" } },
+                { "code": 42210, "meta": { "attribute": "description" } },
+                { "code": 4226 }
+            ]
+        }"#;
+        let res = build_response(422, &[], body.to_vec()).unwrap();
+        let errors = match extract_api_response::<Value>(res).await {
+            Err(Error::API(errors)) => errors,
+            other => panic!("expected API errors, got {:?}", other),
+        };
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors.first().meta()["attribute"], "title");
+        assert!(errors.iter().any(|e| matches!(e.kind(), error::ErrorKind::Unprocessable(error::Unprocessable::UnsupportedAttribute))));
+
+        let res = build_response(422, &[], br#"{"errors": []}"#.to_vec()).unwrap();
+        assert!(matches!(extract_api_response::<Value>(res).await, Err(Error::UnexpectedResponse { .. })));
+    }
+
+    #[tokio::test]
+    async fn bad_bodies_keep_the_raw_body() {
+        let res = build_response(200, &[], b"{\"data\": 5}".to_vec()).unwrap();
+        match extract_api_response::<crate::model::Document<crate::model::Story>>(res).await {
+            Err(Error::Deserialization { status, body, .. }) => {
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(body, "{\"data\": 5}");
+            }
+            other => panic!("expected a deserialization error, got {:?}", other),
+        }
+
+        let long = vec![b'x'; 5000];
+        let res = build_response(200, &[], long).unwrap();
+        match extract_api_response::<Value>(res).await {
+            Err(Error::Deserialization { body, .. }) => assert_eq!(body.len(), MAX_BODY_SNIPPET + 3),
+            other => panic!("expected a deserialization error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limits_carry_retry_after() {
+        let headers = [("Retry-After".to_owned(), "30".to_owned()), ("X-Rate-Limit-Remaining".to_owned(), "0".to_owned())];
+        let body = br#"{"errors": [{"code": 4290}]}"#.to_vec();
+        let res = build_response(429, &headers, body).unwrap();
+        let err = extract_api_response::<Value>(res).await.unwrap_err();
+        assert_eq!(err.retry_after(), Some(std::time::Duration::from_secs(30)));
+        match err {
+            Error::API(errors) => match errors.first().kind() {
+                error::ErrorKind::RateLimited(Some(rl)) => assert_eq!(rl.remaining, Some(0)),
+                other => panic!("expected a rate limit, got {:?}", other),
+            },
+            other => panic!("expected an API error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_errors_do_not_panic() {
+        for body in &[r#"{"unexpected": true}"#, r#"{"errors": [{"code": "nope"}]}"#] {
+            let res = build_response(400, &[], body.as_bytes().to_vec()).unwrap();
+            match extract_api_response::<Value>(res).await {
+                Err(Error::Deserialization { status, body: kept, .. }) => {
+                    assert_eq!(status, StatusCode::BAD_REQUEST);
+                    assert_eq!(&kept, body);
+                }
+                other => panic!("expected a deserialization error, got {:?}", other),
+            }
+        }
+
+        let res = build_response(400, &[], br#"{"errors": []}"#.to_vec()).unwrap();
+        assert!(matches!(extract_api_response::<Value>(res).await, Err(Error::UnexpectedResponse { status: StatusCode::BAD_REQUEST, .. })));
+    }
+
+    #[tokio::test]
+    async fn server_errors_keep_status_and_body() {
+        let headers = [("Content-Type".to_owned(), "text/html".to_owned())];
+        let res = build_response(502, &headers, b"<html>Bad Gateway</html>".to_vec()).unwrap();
+        match extract_api_response::<Value>(res).await {
+            Err(Error::Server { status, body, .. }) => {
+                assert_eq!(status, StatusCode::BAD_GATEWAY);
+                assert_eq!(body, "<html>Bad Gateway</html>");
+            }
+            other => panic!("expected a server error, got {:?}", other),
+        }
+
+        let res = build_response(500, &[], br#"{"error": "oops"}"#.to_vec()).unwrap();
+        let err = extract_api_response::<Value>(res).await.unwrap_err();
+        assert!(matches!(err, Error::Server { status: StatusCode::INTERNAL_SERVER_ERROR, .. }));
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn html_client_errors_are_request_errors() {
+        let headers = [("Content-Type".to_owned(), "text/html".to_owned())];
+        let res = build_response(403, &headers, b"<html>Forbidden</html>".to_vec()).unwrap();
+        assert!(matches!(extract_api_response::<Value>(res).await, Err(Error::Request(_))));
     }
 }
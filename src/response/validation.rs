@@ -0,0 +1,161 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains [ValidationReport], which collects the problems FimFic found with the attributes of
+//! a create or update request.
+
+use crate::response::error::{APIError, APIErrors, Error, ErrorKind, ErrorMeta, Unprocessable};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The validation problems reported for a create or update request, grouped by attribute, so
+/// frontends can show each message next to the field it belongs to.
+///
+/// Built from the errors of a response about the attributes or parameters of the request, such as
+/// [Unprocessable::InvalidAttribute]; other errors, including authentication, pagination, and
+/// sorting errors, are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    fields: BTreeMap<String, Vec<String>>,
+    general: Vec<String>,
+}
+
+impl ValidationReport {
+    /// Builds a report from the errors of a response.
+    /// Returns [None] if none of them were validation problems.
+    pub fn from_errors(errors: &APIErrors) -> Option<Self> {
+        let mut report = ValidationReport::default();
+        let mut any = false;
+        for e in errors.iter().filter(|e| is_validation_problem(e.kind())) {
+            any = true;
+            report.add(e);
+        }
+        if any {
+            Some(report)
+        } else {
+            None
+        }
+    }
+
+    fn add(&mut self, e: &APIError) {
+        let message = e.detail()
+            .or_else(|| e.title())
+            .map(String::from)
+            .unwrap_or_else(|| e.kind().to_string());
+
+        // Prefer per-attribute messages, e.g. `{"attributes": {"title": "too short"}}`.
+        if let Some(Value::Object(map)) = e.meta().get("attributes") {
+            let mut found = false;
+            for (name, v) in map {
+                let messages: Vec<String> = match v {
+                    Value::String(s) => vec![s.clone()],
+                    Value::Array(items) => items.iter().filter_map(Value::as_str).map(String::from).collect(),
+                    _ => Vec::new(),
+                };
+                if !messages.is_empty() {
+                    found = true;
+                    self.fields.entry(name.clone()).or_default().extend(messages);
+                }
+            }
+            if found {
+                return;
+            }
+        }
+
+        match e.typed_meta() {
+            ErrorMeta::Attribute(name) => self.fields.entry(name).or_default().push(message),
+            _ => self.general.push(message),
+        }
+    }
+
+    /// The messages for every attribute with a problem, by attribute name.
+    pub fn fields(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.fields
+    }
+
+    /// The messages for the given attribute. Empty if the attribute had no problems.
+    pub fn messages_for(&self, attribute: &str) -> &[String] {
+        self.fields.get(attribute).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Problems which were not tied to a specific attribute.
+    pub fn general(&self) -> &[String] {
+        &self.general
+    }
+
+    /// Whether the report contains no problems at all.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.general.is_empty()
+    }
+}
+
+/// Whether the error is about the attributes or parameters of the request.
+fn is_validation_problem(kind: ErrorKind) -> bool {
+    use Unprocessable::*;
+    matches!(kind, ErrorKind::Unprocessable(
+        MissingParameter | InvalidArgument | InvalidAttributes | UnsupportedAttribute | InvalidAttribute
+    ))
+}
+
+impl Error {
+    /// Builds a [ValidationReport] if this error contains validation problems.
+    pub fn validation_report(&self) -> Option<ValidationReport> {
+        match self {
+            Error::API(errors) => ValidationReport::from_errors(errors),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::convert::TryFrom;
+
+    fn errors(values: Vec<Value>) -> APIErrors {
+        let errors: Vec<APIError> = values.into_iter().map(|v| APIError::try_from(v).unwrap()).collect();
+        APIErrors::new(errors)
+    }
+
+    #[test]
+    fn groups_messages_by_attribute() {
+        let errors = errors(vec![
+            json!({ "code": 42210, "detail": "The title is too short.", "meta": { "attribute": "title" } }),
+            json!({ "code": 4225, "meta": { "attributes": { "description": ["Too long.", "Bad BBCode."] } } }),
+            json!({ "code": 4226, "title": "Unsupported attribute", "meta": { "attribute": "colour" } }),
+            json!({ "code": 4221, "detail": "Something else was wrong." }),
+            json!({ "code": 4040 }),
+        ]);
+        let report = ValidationReport::from_errors(&errors).unwrap();
+
+        assert_eq!(report.messages_for("title"), ["The title is too short."]);
+        assert_eq!(report.messages_for("description"), ["Too long.", "Bad BBCode."]);
+        assert_eq!(report.messages_for("colour"), ["Unsupported attribute"]);
+        assert!(report.messages_for("rating").is_empty());
+        assert_eq!(report.general(), ["Something else was wrong."]);
+    }
+
+    #[test]
+    fn no_report_without_validation_errors() {
+        let err: Error = errors(vec![json!({ "code": 4040 })]).into();
+        assert_eq!(err.validation_report(), None);
+
+        // Authentication and pagination problems are 422s too, but not about any attribute.
+        let err: Error = errors(vec![
+            json!({ "code": 4224, "detail": "The authorization header was missing." }),
+            json!({ "code": 4228 }),
+        ]).into();
+        assert_eq!(err.validation_report(), None);
+    }
+
+    #[test]
+    fn ignores_other_unprocessable_errors() {
+        let errors = errors(vec![
+            json!({ "code": 42210, "detail": "The title is too short.", "meta": { "attribute": "title" } }),
+            json!({ "code": 4229, "detail": "The authorization header was malformed." }),
+        ]);
+        let report = ValidationReport::from_errors(&errors).unwrap();
+        assert_eq!(report.messages_for("title"), ["The title is too short."]);
+        assert!(report.general().is_empty());
+    }
+}
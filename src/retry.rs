@@ -0,0 +1,265 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the [RetryPolicy] trait, which decides whether failed requests are retried.
+//!
+//! Clients use [ExponentialBackoff] unless configured otherwise through
+//! [ClientBuilder::retry_policy][crate::client::ClientBuilder::retry_policy].
+
+use crate::response::Error;
+use crate::response::error::ErrorKind;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::Method;
+use std::time::Duration;
+
+/// Rate limit information reported by FimFic alongside a response.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RateLimit {
+    /// The number of requests allowed in the current window.
+    pub limit: Option<u64>,
+    /// The number of requests left in the current window.
+    pub remaining: Option<u64>,
+    /// How long until the current window resets.
+    pub reset: Option<Duration>,
+    /// How long the server asked us to wait before trying again.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimit {
+    /// Parses rate limit information from response headers.
+    /// Returns [None] if the response carried none.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let number = |name: &str| headers.get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok());
+
+        let rl = RateLimit {
+            limit: number("x-rate-limit-limit"),
+            remaining: number("x-rate-limit-remaining"),
+            reset: number("x-rate-limit-reset").map(Duration::from_secs),
+            retry_after: number(RETRY_AFTER.as_str()).map(Duration::from_secs),
+        };
+
+        if rl == RateLimit::default() {
+            None
+        } else {
+            Some(rl)
+        }
+    }
+
+    /// How long to wait before the server will accept another request, if it said.
+    pub fn wait(&self) -> Option<Duration> {
+        self.retry_after.or(self.reset)
+    }
+}
+
+/// What to do after a request fails.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RetryDecision {
+    /// Give up and return the error.
+    Stop,
+    /// Wait for the given duration, then send the request again.
+    RetryAfter(Duration),
+}
+
+/// Decides whether and when a failed request is retried.
+pub trait RetryPolicy: std::fmt::Debug + Send + Sync {
+    /// Called after every failed attempt. `method` is the method of the request, `attempt` is
+    /// the number of attempts made so far, starting from 1, and `rate_limit` is the rate limit
+    /// information of the failed response, if there was a response.
+    fn decide(&self, method: &Method, attempt: u32, error: &Error, rate_limit: Option<&RateLimit>) -> RetryDecision;
+}
+
+/// A [RetryPolicy] which never retries.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NoRetry;
+
+impl RetryPolicy for NoRetry {
+    fn decide(&self, _method: &Method, _attempt: u32, _error: &Error, _rate_limit: Option<&RateLimit>) -> RetryDecision {
+        RetryDecision::Stop
+    }
+}
+
+/// The default [RetryPolicy].
+///
+/// Retries rate limited requests, server errors, and connection failures, doubling the delay
+/// after every attempt. When the server says how long to wait, that is respected instead.
+/// Maintenance downtime is only waited out if [retry_maintenance][Self::retry_maintenance]
+/// is enabled.
+///
+/// Requests which are not idempotent, such as creating a story, may have taken effect even
+/// though they failed, so they are only retried when FimFic certainly did not handle them:
+/// when rate limited or down for maintenance.
+#[derive(Debug, Copy, Clone)]
+pub struct ExponentialBackoff {
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    retry_maintenance: bool,
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        ExponentialBackoff {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            retry_maintenance: false,
+        }
+    }
+}
+
+impl ExponentialBackoff {
+    /// Creates the default policy: 3 retries, starting at 500ms and capped at 60s.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many times a request may be retried.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the delay before the first retry.
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    /// Sets the longest this policy will wait between attempts.
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Sets whether requests failing with [Error::Maintenance] are retried. The wait is the
+    /// interval the server gave, capped at the [max delay][Self::max_delay].
+    pub fn retry_maintenance(mut self, retry_maintenance: bool) -> Self {
+        self.retry_maintenance = retry_maintenance;
+        self
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn decide(&self, method: &Method, attempt: u32, error: &Error, rate_limit: Option<&RateLimit>) -> RetryDecision {
+        if attempt > self.max_retries {
+            return RetryDecision::Stop;
+        }
+
+        if let Error::Maintenance { retry_after, .. } = error {
+            if !self.retry_maintenance {
+                return RetryDecision::Stop;
+            }
+            let delay = retry_after.map(|d| d.min(self.max_delay))
+                .unwrap_or_else(|| self.backoff(attempt));
+            return RetryDecision::RetryAfter(delay);
+        }
+
+        if !error.is_retryable() || !(is_idempotent(method) || is_rate_limited(error)) {
+            return RetryDecision::Stop;
+        }
+
+        // Rate limit headers come with every response, but only say when to retry a 429.
+        let wait = if is_rate_limited(error) {
+            rate_limit.and_then(RateLimit::wait).or_else(|| error.retry_after())
+        } else {
+            None
+        };
+        let delay = wait.map(|d| d.min(self.max_delay))
+            .unwrap_or_else(|| self.backoff(attempt));
+        RetryDecision::RetryAfter(delay)
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE)
+}
+
+fn is_rate_limited(error: &Error) -> bool {
+    match error {
+        Error::API(errors) => errors.iter().any(|e| matches!(e.kind(), ErrorKind::RateLimited(_))),
+        Error::Request(e) => e.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::APIError;
+    use crate::response::error::Trace;
+    use reqwest::header::HeaderValue;
+    use std::convert::TryFrom;
+
+    fn api_error(code: u64) -> Error {
+        APIError::try_from(serde_json::json!({ "code": code })).unwrap().into()
+    }
+
+    #[test]
+    fn parses_rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(RateLimit::from_headers(&headers), None);
+
+        headers.insert("X-Rate-Limit-Remaining", HeaderValue::from_static("0"));
+        headers.insert("Retry-After", HeaderValue::from_static("12"));
+        let rl = RateLimit::from_headers(&headers).unwrap();
+        assert_eq!(rl.remaining, Some(0));
+        assert_eq!(rl.wait(), Some(Duration::from_secs(12)));
+    }
+
+    #[test]
+    fn backoff_doubles_then_stops() {
+        let policy = ExponentialBackoff::new().base_delay(Duration::from_secs(1));
+        let err = api_error(4290);
+        assert_eq!(policy.decide(&Method::GET, 1, &err, None), RetryDecision::RetryAfter(Duration::from_secs(1)));
+        assert_eq!(policy.decide(&Method::GET, 3, &err, None), RetryDecision::RetryAfter(Duration::from_secs(4)));
+        assert_eq!(policy.decide(&Method::GET, 4, &err, None), RetryDecision::Stop);
+    }
+
+    #[test]
+    fn backoff_respects_server_wait() {
+        let rl = RateLimit { retry_after: Some(Duration::from_secs(7)), ..Default::default() };
+        let decision = ExponentialBackoff::new().decide(&Method::GET, 1, &api_error(4290), Some(&rl));
+        assert_eq!(decision, RetryDecision::RetryAfter(Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn server_errors_back_off_despite_rate_limit_headers() {
+        let rl = RateLimit { reset: Some(Duration::from_secs(45)), ..Default::default() };
+        let err = Error::Server { status: reqwest::StatusCode::INTERNAL_SERVER_ERROR, body: String::new(), trace: Trace::default() };
+        let decision = ExponentialBackoff::new().base_delay(Duration::from_secs(1)).decide(&Method::GET, 2, &err, Some(&rl));
+        assert_eq!(decision, RetryDecision::RetryAfter(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn maintenance_is_opt_in() {
+        let err = Error::Maintenance { retry_after: Some(Duration::from_secs(30)), trace: Trace::default() };
+        assert_eq!(ExponentialBackoff::new().decide(&Method::GET, 1, &err, None), RetryDecision::Stop);
+        assert_eq!(ExponentialBackoff::new().retry_maintenance(true).decide(&Method::GET, 1, &err, None),
+                   RetryDecision::RetryAfter(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn writes_are_only_retried_when_not_handled() {
+        let policy = ExponentialBackoff::new().base_delay(Duration::from_secs(1)).retry_maintenance(true);
+        let server = Error::Server { status: reqwest::StatusCode::BAD_GATEWAY, body: String::new(), trace: Trace::default() };
+        assert_eq!(policy.decide(&Method::GET, 1, &server, None), RetryDecision::RetryAfter(Duration::from_secs(1)));
+        assert_eq!(policy.decide(&Method::POST, 1, &server, None), RetryDecision::Stop);
+        assert_eq!(policy.decide(&Method::PATCH, 1, &server, None), RetryDecision::Stop);
+        assert_eq!(policy.decide(&Method::POST, 1, &api_error(4290), None), RetryDecision::RetryAfter(Duration::from_secs(1)));
+        let maintenance = Error::Maintenance { retry_after: Some(Duration::from_secs(30)), trace: Trace::default() };
+        assert_eq!(policy.decide(&Method::POST, 1, &maintenance, None), RetryDecision::RetryAfter(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        assert_eq!(ExponentialBackoff::new().decide(&Method::GET, 1, &api_error(4040), None), RetryDecision::Stop);
+    }
+}
@@ -0,0 +1,402 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the incremental sync layer, which finds out what changed on FimFic since a set of
+//! stories was last seen.
+//!
+//! The last seen state is a [SyncState], which records the `date_modified` of every story and
+//! chapter. It can be persisted with serde or read back from an archive written by the
+//! [Archiver][crate::archive::Archiver]. [sync] compares it against FimFic, only fetching the
+//! chapters of stories which were modified, and returns a [SyncDiff].
+//!
+//! ```no_run
+//! # async fn run(client: fimapi::client::Client) -> Result<(), Box<dyn std::error::Error>> {
+//! use fimapi::sync::{sync, Change, SyncState};
+//!
+//! let mut state = SyncState::from_archive("archive").await?;
+//! let diff = sync(&client, &state, state.story_ids()).await?;
+//! for (story_id, change) in &diff.chapters {
+//!     if let Change::Added(chapter) | Change::Updated(chapter) = change {
+//!         println!("{} of story {} changed", chapter.attributes.title, story_id);
+//!     }
+//! }
+//! state.apply(&diff);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::archive::{parse_id, ArchiveError};
+use crate::client::Client;
+use crate::model::{Chapter, Story};
+use crate::response::error::{ErrorKind, NotFound};
+use crate::response::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+/// The last seen state of a story.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoryState {
+    /// When the story was last modified.
+    pub date_modified: Option<String>,
+    /// When each chapter was last modified, by chapter id.
+    #[serde(default)]
+    pub chapters: BTreeMap<String, Option<String>>,
+}
+
+impl StoryState {
+    /// Records the state of a story and its chapters as they are now.
+    pub fn from_resources(story: &Story, chapters: &[Chapter]) -> Self {
+        StoryState {
+            date_modified: story.attributes.date_modified.clone(),
+            chapters: chapters.iter()
+                .map(|c| (c.id.clone(), c.attributes.date_modified.clone()))
+                .collect(),
+        }
+    }
+}
+
+/// The last seen state of a set of stories.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    /// The state of every known story, by story id.
+    #[serde(default)]
+    pub stories: BTreeMap<u64, StoryState>,
+}
+
+impl SyncState {
+    /// Creates an empty state, in which every story is new.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the state of every story archived in the given directory by an
+    /// [Archiver][crate::archive::Archiver]. Stories whose archive was never completed are
+    /// left out, so they are synced from scratch.
+    pub async fn from_archive(dir: impl AsRef<Path>) -> Result<Self, ArchiveError> {
+        let dir = dir.as_ref();
+        let io_err = |source| ArchiveError::Io { path: dir.to_owned(), source };
+        let mut state = SyncState::new();
+        let mut entries = tokio::fs::read_dir(dir).await.map_err(io_err)?;
+        while let Some(entry) = entries.next_entry().await.map_err(io_err)? {
+            let id = match entry.file_name().to_str().and_then(|n| n.parse::<u64>().ok()) {
+                Some(id) => id,
+                None => continue,
+            };
+            let story: Story = match read_json(&entry.path().join("story.json")).await {
+                Some(story) => story,
+                None => continue,
+            };
+            let mut story_state = StoryState {
+                date_modified: story.attributes.date_modified,
+                chapters: BTreeMap::new(),
+            };
+            if let Ok(mut chapters) = tokio::fs::read_dir(entry.path().join("chapters")).await {
+                while let Ok(Some(chapter)) = chapters.next_entry().await {
+                    if chapter.path().extension().is_some_and(|e| e == "json") {
+                        if let Some(chapter) = read_json::<Chapter>(&chapter.path()).await {
+                            story_state.chapters.insert(chapter.id, chapter.attributes.date_modified);
+                        }
+                    }
+                }
+            }
+            state.stories.insert(id, story_state);
+        }
+        Ok(state)
+    }
+
+    /// Records the state of every story in a [Fimfarchive][crate::fimfarchive::Fimfarchive]
+    /// release, so that syncing only fetches what changed since the release.
+    #[cfg(feature = "fimfarchive")]
+    pub fn from_fimfarchive(stories: &[crate::fimfarchive::ArchivedStory]) -> Self {
+        let stories = stories.iter()
+            .filter_map(|s| Some((s.story.id.parse().ok()?, StoryState::from_resources(&s.story, &s.chapters))))
+            .collect();
+        SyncState { stories }
+    }
+
+    /// The ids of every known story.
+    pub fn story_ids(&self) -> Vec<u64> {
+        self.stories.keys().copied().collect()
+    }
+
+    /// Updates this state with the changes in the diff, so that syncing again only reports
+    /// newer changes.
+    pub fn apply(&mut self, diff: &SyncDiff) {
+        for change in &diff.stories {
+            match change {
+                Change::Added(story) | Change::Updated(story) => {
+                    if let Ok(id) = story.id.parse() {
+                        self.stories.entry(id).or_default().date_modified = story.attributes.date_modified.clone();
+                    }
+                }
+                Change::Removed { id } => {
+                    if let Ok(id) = id.parse::<u64>() {
+                        self.stories.remove(&id);
+                    }
+                }
+            }
+        }
+        for (story_id, change) in &diff.chapters {
+            let story = match self.stories.get_mut(story_id) {
+                Some(story) => story,
+                None => continue,
+            };
+            match change {
+                Change::Added(chapter) | Change::Updated(chapter) => {
+                    story.chapters.insert(chapter.id.clone(), chapter.attributes.date_modified.clone());
+                }
+                Change::Removed { id } => {
+                    story.chapters.remove(id);
+                }
+            }
+        }
+    }
+}
+
+/// A change to a single resource.
+#[derive(Debug, Clone)]
+pub enum Change<T> {
+    /// The resource was not known before.
+    Added(T),
+    /// The resource was modified since it was last seen.
+    Updated(T),
+    /// The resource no longer exists.
+    Removed {
+        /// The id of the removed resource.
+        id: String,
+    },
+}
+
+impl<T> Change<T> {
+    /// The changed resource, unless it was removed.
+    pub fn resource(&self) -> Option<&T> {
+        match self {
+            Change::Added(t) | Change::Updated(t) => Some(t),
+            Change::Removed { .. } => None,
+        }
+    }
+}
+
+/// Everything that changed since a [SyncState] was recorded.
+///
+/// Added and updated chapters include their text.
+#[derive(Debug, Default)]
+pub struct SyncDiff {
+    /// The stories which changed.
+    pub stories: Vec<Change<Story>>,
+    /// The chapters which changed, with the id of the story they belong to.
+    pub chapters: Vec<(u64, Change<Chapter>)>,
+    /// The stories which could not be synced, e.g. because they were made private, with the
+    /// error. [SyncState::apply] leaves them as they were, so they are tried again next time.
+    pub failed: BTreeMap<u64, Error>,
+}
+
+impl SyncDiff {
+    /// Whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.stories.is_empty() && self.chapters.is_empty()
+    }
+}
+
+/// Compares the given stories against the last seen state and returns what changed.
+///
+/// Stories missing from the state are treated as added. Stories which were not modified since
+/// they were last seen cost a single request. Stories in the state which no longer exist are
+/// reported as removed, along with their chapters.
+///
+/// A story which fails to sync does not stop the others; it is reported in
+/// [failed][SyncDiff::failed]. Only authentication errors, which would fail every story, are
+/// returned as an error.
+pub async fn sync(client: &Client, state: &SyncState, story_ids: impl IntoIterator<Item = u64>) -> Result<SyncDiff, Error> {
+    let ids: BTreeSet<u64> = story_ids.into_iter().collect();
+    let results = futures::future::join_all(ids.into_iter()
+        .map(|id| async move { (id, sync_story(client, id, state.stories.get(&id)).await) })).await;
+
+    let mut diff = SyncDiff::default();
+    for (id, result) in results {
+        match result {
+            Ok((story, chapters)) => {
+                diff.stories.extend(story);
+                diff.chapters.extend(chapters);
+            }
+            Err(e) if e.is_auth_error() => return Err(e),
+            Err(e) => {
+                diff.failed.insert(id, e);
+            }
+        }
+    }
+    Ok(diff)
+}
+
+type StoryDiff = (Option<Change<Story>>, Vec<(u64, Change<Chapter>)>);
+
+async fn sync_story(client: &Client, id: u64, previous: Option<&StoryState>) -> Result<StoryDiff, Error> {
+    let story = match (client.story(id).await, previous) {
+        (Ok(story), _) => story,
+        (Err(e), Some(previous)) if is_missing(&e) => {
+            let chapters = previous.chapters.keys()
+                .map(|c| (id, Change::Removed { id: c.clone() }))
+                .collect();
+            return Ok((Some(Change::Removed { id: id.to_string() }), chapters));
+        }
+        (Err(e), _) => return Err(e),
+    };
+
+    let empty = StoryState::default();
+    let previous_chapters = match previous {
+        None => &empty,
+        Some(p) if p.date_modified.is_some() && p.date_modified == story.attributes.date_modified => return Ok((None, Vec::new())),
+        Some(p) => p,
+    };
+
+    let listed = client.story_chapters(id).await?;
+    let changed = futures::future::try_join_all(listed.iter()
+        .filter_map(|c| match previous_chapters.chapters.get(&c.id) {
+            None => Some((c, true)),
+            Some(seen) if seen.is_none() || *seen != c.attributes.date_modified => Some((c, false)),
+            Some(_) => None,
+        })
+        .map(|(c, added)| async move {
+            let chapter = client.chapter(parse_id(&c.id)?).await?;
+            Ok::<_, Error>(if added { Change::Added(chapter) } else { Change::Updated(chapter) })
+        })).await?;
+
+    let listed_ids: BTreeSet<&str> = listed.iter().map(|c| c.id.as_str()).collect();
+    let mut chapters: Vec<(u64, Change<Chapter>)> = changed.into_iter().map(|c| (id, c)).collect();
+    chapters.extend(previous_chapters.chapters.keys()
+        .filter(|c| !listed_ids.contains(c.as_str()))
+        .map(|c| (id, Change::Removed { id: c.clone() })));
+
+    let change = if previous.is_some() { Change::Updated(story) } else { Change::Added(story) };
+    Ok((Some(change), chapters))
+}
+
+pub(crate) fn is_missing(e: &Error) -> bool {
+    match e {
+        Error::API(errors) => errors.iter().any(|e| matches!(e.kind(), ErrorKind::NotFound(NotFound::ResourceNotFound))),
+        _ => false,
+    }
+}
+
+async fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Option<T> {
+    let bytes = tokio::fs::read(path).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::Archiver;
+    use crate::testing::{fixtures, MockClient};
+
+    fn mock() -> MockClient {
+        let mock = MockClient::new();
+        mock.on_get("/stories/1", fixtures::STORY)
+            .on_get("/stories/1/chapters", fixtures::CHAPTERS)
+            .on_get("/chapters/11", fixtures::CHAPTER)
+            .on_get("/chapters/12", fixtures::SECOND_CHAPTER);
+        mock
+    }
+
+    fn seen(story: &str, chapters: &[(&str, &str)]) -> StoryState {
+        StoryState {
+            date_modified: Some(story.to_owned()),
+            chapters: chapters.iter().map(|(id, d)| ((*id).to_owned(), Some((*d).to_owned()))).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn new_stories_are_added() {
+        let mock = mock();
+        let diff = sync(&mock.client(), &SyncState::new(), vec![1]).await.unwrap();
+        assert!(matches!(diff.stories.as_slice(), [Change::Added(_)]));
+        assert_eq!(diff.chapters.len(), 2);
+        assert!(diff.chapters.iter().all(|(s, c)| *s == 1 && matches!(c, Change::Added(_))));
+
+        let mut state = SyncState::new();
+        state.apply(&diff);
+        assert_eq!(state.stories[&1], seen("2020-06-01T12:00:00+00:00", &[
+            ("11", "2020-05-01T12:00:00+00:00"),
+            ("12", "2020-05-30T12:00:00+00:00"),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn unmodified_stories_are_skipped() {
+        let mock = mock();
+        let mut state = SyncState::new();
+        state.stories.insert(1, seen("2020-06-01T12:00:00+00:00", &[]));
+
+        let diff = sync(&mock.client(), &state, state.story_ids()).await.unwrap();
+        assert!(diff.is_empty());
+        assert_eq!(mock.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn modified_stories_diff_their_chapters() {
+        let mock = mock();
+        let mut state = SyncState::new();
+        state.stories.insert(1, seen("2020-05-01T12:00:00+00:00", &[
+            ("10", "2020-04-01T12:00:00+00:00"),
+            ("11", "2020-05-01T12:00:00+00:00"),
+            ("12", "2020-05-01T12:00:00+00:00"),
+        ]));
+
+        let diff = sync(&mock.client(), &state, state.story_ids()).await.unwrap();
+        assert!(matches!(diff.stories.as_slice(), [Change::Updated(_)]));
+        let changes: Vec<(&str, bool)> = diff.chapters.iter()
+            .map(|(_, c)| match c {
+                Change::Updated(c) => (c.id.as_str(), true),
+                Change::Removed { id } => (id.as_str(), false),
+                Change::Added(c) => panic!("chapter {} should not be new", c.id),
+            })
+            .collect();
+        assert_eq!(changes, vec![("12", true), ("10", false)]);
+        assert!(!mock.requests().iter().any(|r| r.path == "/chapters/11"));
+    }
+
+    #[tokio::test]
+    async fn missing_stories_are_removed() {
+        let mock = MockClient::new();
+        mock.on("GET", "/stories/1", 404, fixtures::NOT_FOUND);
+        let mut state = SyncState::new();
+        state.stories.insert(1, seen("2020-06-01T12:00:00+00:00", &[("11", "2020-05-01T12:00:00+00:00")]));
+
+        let diff = sync(&mock.client(), &state, state.story_ids()).await.unwrap();
+        assert!(matches!(diff.stories.as_slice(), [Change::Removed { id }] if id == "1"));
+        assert!(matches!(diff.chapters.as_slice(), [(1, Change::Removed { id })] if id == "11"));
+
+        state.apply(&diff);
+        assert!(state.stories.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_stories_do_not_stop_the_others() {
+        let mock = mock();
+        mock.on("GET", "/stories/2", 403, r#"{"errors":[{"status":"403","code":4030}]}"#);
+        let mut state = SyncState::new();
+        state.stories.insert(2, seen("2020-06-01T12:00:00+00:00", &[]));
+
+        let diff = sync(&mock.client(), &state, vec![1, 2]).await.unwrap();
+        assert!(matches!(diff.stories.as_slice(), [Change::Added(s)] if s.id == "1"));
+        assert!(diff.failed[&2].is_client_error());
+
+        state.apply(&diff);
+        assert_eq!(state.story_ids(), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn reads_state_from_archives() {
+        let dest = std::env::temp_dir().join(format!("fimapi-sync-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dest);
+        let mock = mock();
+        let client = mock.client();
+        Archiver::new().include_cover(false).archive_story(&client, 1, &dest).await.unwrap();
+
+        let state = SyncState::from_archive(&dest).await.unwrap();
+        assert_eq!(state.stories[&1].chapters.len(), 2);
+        assert!(sync(&client, &state, state.story_ids()).await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dest).unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Canned FimFic API responses, shaped like the real thing.
+//!
+//! The success fixtures describe story `1` by user `2`, so they can be registered together on a
+//! [MockClient][super::MockClient].
+
+/// A token response for the client credentials flow.
+pub const TOKEN: &str = r#"{
+    "access_token": "mock_token",
+    "token_type": "Bearer"
+}"#;
+
+/// A document containing story `1`, written by user `2`.
+pub const STORY: &str = r#"{
+    "data": {
+        "id": "1",
+        "type": "story",
+        "attributes": {
+            "title": "The Mock Story",
+            "short_description": "A story that never was.",
+            "description": "[b]A story[/b] that never was.",
+            "description_html": "<p><b>A story</b> that never was.</p>",
+            "date_modified": "2020-06-01T12:00:00+00:00",
+            "date_updated": "2020-05-30T12:00:00+00:00",
+            "date_published": "2020-05-01T12:00:00+00:00",
+            "published": true,
+            "completion_status": "incomplete",
+            "content_rating": "everyone",
+            "cover_image": {
+                "thumbnail": "https://cdn-img.fimfiction.net/story/mock/thumbnail.png",
+                "medium": "https://cdn-img.fimfiction.net/story/mock/medium.png",
+                "large": "https://cdn-img.fimfiction.net/story/mock/large.png",
+                "full": "https://cdn-img.fimfiction.net/story/mock/full.png"
+            },
+            "color": { "hex": "6b9bd1", "rgb": [107, 155, 209] },
+            "num_chapters": 2,
+            "num_words": 4200,
+            "num_views": 100,
+            "total_num_views": 150,
+            "num_comments": 3,
+            "num_likes": 10,
+            "num_dislikes": 1
+        },
+        "relationships": {
+            "author": { "data": { "type": "user", "id": "2" } },
+            "tags": { "data": [ { "type": "story_tag", "id": "7" } ] }
+        }
+    }
+}"#;
+
+/// A document containing user `2`.
+pub const USER: &str = r#"{
+    "data": {
+        "id": "2",
+        "type": "user",
+        "attributes": {
+            "name": "Mock Author",
+            "bio": "Writes [i]mock[/i] stories.",
+            "bio_html": "<p>Writes <i>mock</i> stories.</p>",
+            "avatar": {
+                "64": "https://cdn-img.fimfiction.net/user/mock-64.png"
+            },
+            "color": { "hex": "d16b6b", "rgb": [209, 107, 107] },
+            "num_followers": 5,
+            "num_stories": 1,
+            "num_blog_posts": 0,
+            "date_joined": "2019-01-01T00:00:00+00:00"
+        }
+    }
+}"#;
+
+/// A document listing the chapters `11` and `12` of story `1`, without their text.
+pub const CHAPTERS: &str = r#"{
+    "data": [
+        {
+            "id": "11",
+            "type": "chapter",
+            "attributes": {
+                "chapter_number": 1,
+                "title": "The Beginning",
+                "published": true,
+                "num_words": 2000,
+                "num_views": 100,
+                "date_published": "2020-05-01T12:00:00+00:00",
+                "date_modified": "2020-05-01T12:00:00+00:00"
+            },
+            "relationships": {
+                "story": { "data": { "type": "story", "id": "1" } }
+            }
+        },
+        {
+            "id": "12",
+            "type": "chapter",
+            "attributes": {
+                "chapter_number": 2,
+                "title": "The End",
+                "published": true,
+                "num_words": 2200,
+                "num_views": 50,
+                "date_published": "2020-05-30T12:00:00+00:00",
+                "date_modified": "2020-05-30T12:00:00+00:00"
+            },
+            "relationships": {
+                "story": { "data": { "type": "story", "id": "1" } }
+            }
+        }
+    ]
+}"#;
+
+/// A document listing the bookshelves `21` and `22` of user `2`.
+pub const BOOKSHELVES: &str = r#"{
+    "data": [
+        {
+            "id": "21",
+            "type": "bookshelf",
+            "attributes": {
+                "name": "Favourites",
+                "description": "The best of the best, \"truly\".",
+                "privacy": "public",
+                "num_stories": 2,
+                "order": 1
+            },
+            "relationships": {
+                "user": { "data": { "type": "user", "id": "2" } }
+            }
+        },
+        {
+            "id": "22",
+            "type": "bookshelf",
+            "attributes": {
+                "name": "Read Later",
+                "privacy": "private",
+                "num_stories": 0,
+                "order": 2
+            },
+            "relationships": {
+                "user": { "data": { "type": "user", "id": "2" } }
+            }
+        }
+    ]
+}"#;
+
+/// A document listing the items of bookshelf `21`: stories `1` and `3`.
+pub const BOOKSHELF_ITEMS: &str = r#"{
+    "data": [
+        {
+            "id": "31",
+            "type": "bookshelf_item",
+            "attributes": { "date_added": "2020-06-01T12:00:00+00:00" },
+            "relationships": {
+                "story": { "data": { "type": "story", "id": "1" } }
+            }
+        },
+        {
+            "id": "32",
+            "type": "bookshelf_item",
+            "attributes": { "date_added": "2020-06-02T12:00:00+00:00" },
+            "relationships": {
+                "story": { "data": { "type": "story", "id": "3" } }
+            }
+        }
+    ]
+}"#;
+
+/// A document listing the chapters read by user `2`: chapters `11` and `12` of story `1`, and
+/// a chapter of story `3`.
+pub const CHAPTER_READS: &str = r#"{
+    "data": [
+        {
+            "id": "51",
+            "type": "chapter_read",
+            "attributes": { "date_read": "2020-06-01T12:00:00+00:00" },
+            "relationships": {
+                "chapter": { "data": { "type": "chapter", "id": "11" } },
+                "story": { "data": { "type": "story", "id": "1" } }
+            }
+        },
+        {
+            "id": "52",
+            "type": "chapter_read",
+            "attributes": { "date_read": "2020-06-03T12:00:00+00:00" },
+            "relationships": {
+                "chapter": { "data": { "type": "chapter", "id": "12" } },
+                "story": { "data": { "type": "story", "id": "1" } }
+            }
+        },
+        {
+            "id": "53",
+            "type": "chapter_read",
+            "attributes": { "date_read": "2020-06-02T12:00:00+00:00" },
+            "relationships": {
+                "chapter": { "data": { "type": "chapter", "id": "31" } },
+                "story": { "data": { "type": "story", "id": "3" } }
+            }
+        }
+    ]
+}"#;
+
+/// A document containing chapter `11` of story `1`, with its text.
+pub const CHAPTER: &str = r#"{
+    "data": {
+        "id": "11",
+        "type": "chapter",
+        "attributes": {
+            "chapter_number": 1,
+            "title": "The Beginning",
+            "published": true,
+            "num_words": 2000,
+            "num_views": 100,
+            "date_published": "2020-05-01T12:00:00+00:00",
+            "date_modified": "2020-05-01T12:00:00+00:00",
+            "content": "[b]Once[/b] upon a time.",
+            "content_html": "<p><b>Once</b> upon a time.</p>"
+        },
+        "relationships": {
+            "story": { "data": { "type": "story", "id": "1" } }
+        }
+    }
+}"#;
+
+/// A document containing chapter `12` of story `1`, with its text.
+pub const SECOND_CHAPTER: &str = r#"{
+    "data": {
+        "id": "12",
+        "type": "chapter",
+        "attributes": {
+            "chapter_number": 2,
+            "title": "The End",
+            "published": true,
+            "num_words": 2200,
+            "num_views": 50,
+            "date_published": "2020-05-30T12:00:00+00:00",
+            "date_modified": "2020-05-30T12:00:00+00:00",
+            "content": "And they lived [i]happily[/i] ever after.",
+            "content_html": "<p>And they lived <i>happily</i> ever after.</p>"
+        },
+        "relationships": {
+            "story": { "data": { "type": "story", "id": "1" } }
+        }
+    }
+}"#;
+
+/// A 404 response for a resource which does not exist.
+pub const NOT_FOUND: &str = r#"{
+    "errors": [
+        {
+            "status": "404",
+            "title": "Not Found",
+            "detail": "The requested resource was not found.",
+            "code": 4040
+        }
+    ]
+}"#;
+
+/// A 404 response for an endpoint which does not exist.
+pub const ENDPOINT_MISSING: &str = r#"{
+    "errors": [
+        {
+            "status": "404",
+            "title": "Not Found",
+            "detail": "The requested endpoint does not exist.",
+            "code": 4042
+        }
+    ]
+}"#;
+
+/// A 403 response for an invalid token.
+pub const INVALID_TOKEN: &str = r#"{
+    "errors": [
+        {
+            "status": "403",
+            "title": "Forbidden",
+            "detail": "The token used for the request was not valid.",
+            "code": 4032
+        }
+    ]
+}"#;
+
+/// A 429 response for a rate limited client.
+pub const RATE_LIMITED: &str = r#"{
+    "errors": [
+        {
+            "status": "429",
+            "title": "Too Many Requests",
+            "detail": "You are being rate limited.",
+            "code": 4290
+        }
+    ]
+}"#;
+
+/// A 422 response for a PATCH/POST with invalid attributes.
+pub const INVALID_ATTRIBUTE: &str = r#"{
+    "errors": [
+        {
+            "status": "422",
+            "title": "Invalid Attribute",
+            "detail": "The title must be at least 3 characters long.",
+            "code": 42210,
+            "meta": { "attribute": "title" }
+        }
+    ]
+}"#;
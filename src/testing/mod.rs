@@ -0,0 +1,248 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains helpers for testing code which uses this crate without touching the network.
+//!
+//! A [MockClient] answers requests with canned responses, such as the ones in [fixtures], and
+//! hands out [Client]s which talk to it.
+//!
+//! ```
+//! # async fn run() -> Result<(), fimapi::response::Error> {
+//! use fimapi::testing::{fixtures, MockClient};
+//!
+//! let mock = MockClient::new();
+//! mock.on_get("/stories/1", fixtures::STORY)
+//!     .on("GET", "/stories/2", 404, fixtures::NOT_FOUND);
+//!
+//! let client = mock.client();
+//! assert_eq!(client.story(1).await?.attributes.title, "The Mock Story");
+//! assert!(client.story(2).await.is_err());
+//! assert_eq!(mock.requests().len(), 2);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod fixtures;
+
+use crate::client::{Client, ClientBuilder, BASE_URL};
+use crate::retry::NoRetry;
+use crate::response::Error;
+use crate::transport::{build_response, Transport};
+use futures::future::BoxFuture;
+use reqwest::{Request, Response};
+use std::sync::{Arc, Mutex};
+
+/// The bearer token given to clients created by a [MockClient].
+pub const MOCK_TOKEN: &str = "Bearer mock_token";
+
+#[derive(Debug)]
+struct Route {
+    method: String,
+    path: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Route {
+    fn matches(&self, method: &str, path: &str) -> bool {
+        if self.method != method {
+            return false;
+        }
+
+        // Routes without a query string match any query.
+        if self.path.contains('?') {
+            self.path == path
+        } else {
+            self.path == path.split('?').next().unwrap_or_default()
+        }
+    }
+}
+
+/// A request received by a [MockClient].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockRequest {
+    /// The HTTP method, e.g. `GET`.
+    pub method: String,
+    /// The path and query, relative to [BASE_URL] for API requests or absolute otherwise.
+    pub path: String,
+    /// The headers of the request, with lowercase names, in the order they were set.
+    pub headers: Vec<(String, String)>,
+    /// The body of the request, if it had one.
+    pub body: Option<String>,
+}
+
+impl MockRequest {
+    /// Returns the value of the first header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    routes: Vec<Route>,
+    requests: Vec<MockRequest>,
+}
+
+/// A fake FimFic server which answers requests with canned responses.
+///
+/// Responses are registered per method and path. If several responses are registered for the
+/// same request, they are returned in order and the last one is repeated forever. Requests
+/// which match nothing receive a 404 [ENDPOINT_MISSING][fixtures::ENDPOINT_MISSING] response,
+/// just like the real API.
+///
+/// Clones share the same routes and request log.
+#[derive(Debug, Clone, Default)]
+pub struct MockClient {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockClient {
+    /// Creates a mock with no routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a response for the given method and path. The path is relative to [BASE_URL]
+    /// if it starts with `/`. If it contains a query string, the query must match exactly.
+    pub fn on(&self, method: &str, path: &str, status: u16, body: impl Into<String>) -> &Self {
+        self.on_with_headers(method, path, status, &[], body)
+    }
+
+    /// Like [on][Self::on], but also sets response headers.
+    pub fn on_with_headers(&self, method: &str, path: &str, status: u16, headers: &[(&str, &str)], body: impl Into<String>) -> &Self {
+        self.state.lock().unwrap().routes.push(Route {
+            method: method.to_ascii_uppercase(),
+            path: path.to_owned(),
+            status,
+            headers: headers.iter().map(|(k, v)| ((*k).to_owned(), (*v).to_owned())).collect(),
+            body: body.into(),
+        });
+        self
+    }
+
+    /// Registers a successful response for a GET request.
+    pub fn on_get(&self, path: &str, body: impl Into<String>) -> &Self {
+        self.on("GET", path, 200, body)
+    }
+
+    /// Creates a [Client] which sends its requests to this mock.
+    /// Failed requests are never retried, so error responses are returned immediately.
+    pub fn client(&self) -> Client {
+        self.client_builder()
+            .retry_policy(NoRetry)
+            .build_with_token(MOCK_TOKEN)
+            .expect("failed to initialize the HTTP client")
+    }
+
+    /// Creates a [ClientBuilder] which sends its requests to this mock, for tests which need
+    /// to configure the client further.
+    pub fn client_builder(&self) -> ClientBuilder {
+        Client::builder().transport(self.clone())
+    }
+
+    /// Returns every request received so far, in order.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Transport for MockClient {
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response, Error>> {
+        let url = request.url().as_str();
+        let path = url.strip_prefix(BASE_URL).unwrap_or(url).to_owned();
+        let method = request.method().as_str().to_owned();
+        let headers = request.headers().iter()
+            .map(|(k, v)| (k.as_str().to_owned(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+            .collect();
+        let body = request.body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| String::from_utf8_lossy(b).into_owned());
+
+        let mut state = self.state.lock().unwrap();
+        state.requests.push(MockRequest {
+            method: method.clone(),
+            path: path.clone(),
+            headers,
+            body,
+        });
+
+        let matching: Vec<usize> = state.routes.iter()
+            .enumerate()
+            .filter(|(_, r)| r.matches(&method, &path))
+            .map(|(i, _)| i)
+            .collect();
+        let res = match matching.as_slice() {
+            [] => build_response(404, &[], fixtures::ENDPOINT_MISSING.into()),
+            [only] => {
+                let route = &state.routes[*only];
+                build_response(route.status, &route.headers, route.body.clone().into_bytes())
+            }
+            [first, ..] => {
+                let route = state.routes.remove(*first);
+                build_response(route.status, &route.headers, route.body.into_bytes())
+            }
+        };
+        Box::pin(futures::future::ready(res))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::error::{ErrorKind, NotFound};
+
+    #[tokio::test]
+    async fn serves_fixtures() {
+        let mock = MockClient::new();
+        mock.on_get("/stories/1", fixtures::STORY)
+            .on_get("/users/2", fixtures::USER);
+        let client = mock.client();
+
+        let story = client.story(1).await.unwrap();
+        assert_eq!(story.attributes.title, "The Mock Story");
+        assert_eq!(story.related_id("author"), Some("2"));
+
+        let user = client.user(2).await.unwrap();
+        assert_eq!(user.attributes.name, "Mock Author");
+
+        assert_eq!(mock.requests()[0].path, "/stories/1");
+        assert_eq!(mock.requests()[0].header("Authorization"), Some(MOCK_TOKEN));
+    }
+
+    #[tokio::test]
+    async fn unmatched_requests_are_missing_endpoints() {
+        let client = MockClient::new().client();
+        match client.user(1).await {
+            Err(Error::API(e)) => assert!(matches!(e.first().kind(), ErrorKind::NotFound(NotFound::EndpointMissing))),
+            other => panic!("expected an API error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_routes_are_played_in_order() {
+        let mock = MockClient::new();
+        mock.on("GET", "/stories/1", 429, fixtures::RATE_LIMITED)
+            .on_get("/stories/1", fixtures::STORY);
+        let client = mock.client();
+
+        assert!(client.story(1).await.is_err());
+        assert!(client.story(1).await.is_ok());
+        assert!(client.story(1).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_are_retried() {
+        let mock = MockClient::new();
+        mock.on_with_headers("GET", "/stories/1", 429, &[("Retry-After", "0")], fixtures::RATE_LIMITED)
+            .on_get("/stories/1", fixtures::STORY);
+        let client = mock.client_builder()
+            .build_with_token(MOCK_TOKEN)
+            .unwrap();
+
+        assert!(client.story(1).await.is_ok());
+        assert_eq!(mock.requests().len(), 2);
+    }
+}
@@ -0,0 +1,41 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the [Transport] abstraction the [Client][crate::client::Client] uses to talk to the
+//! FimFic servers.
+//!
+//! By default, requests are sent with a [reqwest::Client], but any [Transport] may be plugged in
+//! through the [ClientBuilder][crate::client::ClientBuilder]. This is mostly useful for testing.
+
+#[cfg(feature = "vcr")]
+pub mod vcr;
+
+use crate::response::Error;
+use futures::future::BoxFuture;
+use reqwest::{Request, Response};
+
+/// Something that can execute HTTP requests on behalf of a [Client][crate::client::Client].
+///
+/// Implementations should only return an [Err] when no response could be obtained at all;
+/// error statuses are interpreted by the client.
+pub trait Transport: std::fmt::Debug + Send + Sync {
+    /// Executes the request.
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response, Error>>;
+}
+
+impl Transport for reqwest::Client {
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response, Error>> {
+        Box::pin(async move { Ok(reqwest::Client::execute(self, request).await?) })
+    }
+}
+
+/// Builds a [Response] from a status, headers, and an already buffered body.
+#[cfg(any(test, feature = "vcr", feature = "testing"))]
+pub(crate) fn build_response(status: u16, headers: &[(String, String)], body: Vec<u8>) -> Result<Response, Error> {
+    let mut builder = http::Response::builder().status(status);
+    for (name, value) in headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    builder.body(body)
+        .map(Response::from)
+        .map_err(Error::transport)
+}
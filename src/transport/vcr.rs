@@ -0,0 +1,387 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains a record/replay [Transport] for testing against real FimFic responses.
+//!
+//! A [RecordingTransport] forwards requests to another transport and writes every interaction
+//! to a [Cassette]. The cassette can be saved to disk and later played back with a
+//! [ReplayTransport], which never touches the network. Secrets (client secrets, access tokens,
+//! authorization headers, cookies) are scrubbed before they are recorded, so cassettes are safe
+//! to commit.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use fimapi::client::Client;
+//! use fimapi::transport::vcr::{RecordingTransport, ReplayTransport};
+//!
+//! // Record once, with real credentials...
+//! let recorder = RecordingTransport::new(reqwest::Client::new());
+//! let client = Client::builder()
+//!     .transport(recorder.clone())
+//!     .build_with_credentials("id", "secret")
+//!     .await?;
+//! recorder.cassette().save("tests/cassettes/token.json")?;
+//!
+//! // ...and replay in CI without them.
+//! let client = Client::builder()
+//!     .transport(ReplayTransport::load("tests/cassettes/token.json")?)
+//!     .build_with_credentials("id", "secret")
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::response::Error;
+use crate::transport::{build_response, Transport};
+use futures::future::BoxFuture;
+use reqwest::{Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Keys whose values are replaced in recorded query strings, form bodies, and JSON bodies.
+const SCRUBBED_KEYS: &[&str] = &["client_secret", "access_token", "refresh_token", "password"];
+/// Headers which are never written to a cassette.
+const SCRUBBED_HEADERS: &[&str] = &["authorization", "set-cookie", "cookie"];
+/// The value recorded in place of a scrubbed secret.
+const REDACTED: &str = "REDACTED";
+
+/// A recorded request body or response body.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RecordedBody {
+    /// A body which was valid UTF-8.
+    Text(String),
+    /// A body which was not valid UTF-8, such as an image.
+    Binary(Vec<u8>),
+}
+
+impl RecordedBody {
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(s) => RecordedBody::Text(s),
+            Err(e) => RecordedBody::Binary(e.into_bytes()),
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            RecordedBody::Text(s) => s.into_bytes(),
+            RecordedBody::Binary(b) => b,
+        }
+    }
+}
+
+/// The parts of a request a [ReplayTransport] matches on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// The HTTP method, e.g. `GET`.
+    pub method: String,
+    /// The full URL, with secrets scrubbed.
+    pub url: String,
+    /// The request body, with secrets scrubbed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<RecordedBody>,
+}
+
+impl RecordedRequest {
+    fn from_request(request: &Request) -> Self {
+        let mut url = request.url().clone();
+        if url.query().is_some() {
+            let pairs: Vec<(String, String)> = url.query_pairs()
+                .map(|(k, v)| {
+                    let v = if is_scrubbed_key(&k) { REDACTED.into() } else { v.into_owned() };
+                    (k.into_owned(), v)
+                })
+                .collect();
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+
+        let body = request.body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| scrub_body(b.to_vec()));
+
+        RecordedRequest {
+            method: request.method().as_str().to_owned(),
+            url: url.into(),
+            body,
+        }
+    }
+}
+
+/// A recorded response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    /// The HTTP status code.
+    pub status: u16,
+    /// The response headers, with sensitive headers removed.
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    /// The response body, with secrets scrubbed.
+    pub body: RecordedBody,
+}
+
+/// A single request and the response it received.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    /// The request which was sent.
+    pub request: RecordedRequest,
+    /// The response which was received.
+    pub response: RecordedResponse,
+}
+
+/// An ordered list of recorded [Interaction]s.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    /// Creates an empty cassette.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a cassette from a JSON file.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        serde_json::from_slice(&data).map_err(std::io::Error::from)
+    }
+
+    /// Writes the cassette to a JSON file, replacing it if it exists.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(std::io::Error::from)?;
+        std::fs::write(path, data)
+    }
+
+    /// The recorded interactions, in the order they happened.
+    pub fn interactions(&self) -> &[Interaction] {
+        &self.interactions
+    }
+
+    /// Appends an interaction to the cassette.
+    pub fn push(&mut self, interaction: Interaction) {
+        self.interactions.push(interaction);
+    }
+}
+
+/// A [Transport] which forwards requests to another transport and records them to a [Cassette].
+///
+/// Clones share the same cassette, so keep one around to [save][Cassette::save] the cassette
+/// after handing the transport to a client.
+#[derive(Debug)]
+pub struct RecordingTransport<T> {
+    inner: Arc<T>,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl<T> Clone for RecordingTransport<T> {
+    fn clone(&self) -> Self {
+        RecordingTransport {
+            inner: self.inner.clone(),
+            cassette: self.cassette.clone(),
+        }
+    }
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    /// Creates a recorder wrapping the given transport with an empty cassette.
+    pub fn new(inner: T) -> Self {
+        RecordingTransport {
+            inner: Arc::new(inner),
+            cassette: Default::default(),
+        }
+    }
+
+    /// Returns a copy of everything recorded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response, Error>> {
+        Box::pin(async move {
+            let recorded_request = RecordedRequest::from_request(&request);
+            let res = self.inner.execute(request).await?;
+
+            let status = res.status().as_u16();
+            let headers: Vec<(String, String)> = res.headers().iter()
+                .filter_map(|(k, v)| Some((k.as_str().to_owned(), v.to_str().ok()?.to_owned())))
+                .collect();
+            let body = res.bytes().await?.to_vec();
+
+            let recorded_headers = headers.iter()
+                .filter(|(k, _)| !SCRUBBED_HEADERS.contains(&k.to_ascii_lowercase().as_str()))
+                .cloned()
+                .collect();
+            self.cassette.lock().unwrap().push(Interaction {
+                request: recorded_request,
+                response: RecordedResponse {
+                    status,
+                    headers: recorded_headers,
+                    body: scrub_body(body.clone()),
+                },
+            });
+
+            build_response(status, &headers, body)
+        })
+    }
+}
+
+/// Returned by a [ReplayTransport] when a request does not match any unplayed [Interaction].
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("No recorded interaction matches {method} {url}")]
+pub struct UnmatchedRequest {
+    /// The method of the unmatched request.
+    pub method: String,
+    /// The scrubbed URL of the unmatched request.
+    pub url: String,
+}
+
+/// A [Transport] which answers requests from a [Cassette] without using the network.
+///
+/// Each interaction is played back at most once, in recorded order, so repeated identical
+/// requests receive the responses they originally received.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    interactions: Vec<Interaction>,
+    played: Mutex<Vec<bool>>,
+}
+
+impl ReplayTransport {
+    /// Creates a transport which plays back the given cassette.
+    pub fn new(cassette: Cassette) -> Self {
+        let played = Mutex::new(vec![false; cassette.interactions.len()]);
+        ReplayTransport {
+            interactions: cassette.interactions,
+            played,
+        }
+    }
+
+    /// Loads a cassette from a JSON file and plays it back.
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Cassette::load(path).map(Self::new)
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn execute(&self, request: Request) -> BoxFuture<'_, Result<Response, Error>> {
+        let wanted = RecordedRequest::from_request(&request);
+        let found = {
+            let mut played = self.played.lock().unwrap();
+            let idx = self.interactions.iter()
+                .enumerate()
+                .position(|(i, it)| !played[i] && it.request == wanted);
+            if let Some(i) = idx {
+                played[i] = true;
+            }
+            idx
+        };
+
+        let res = match found {
+            Some(i) => {
+                let recorded = self.interactions[i].response.clone();
+                build_response(recorded.status, &recorded.headers, recorded.body.into_bytes())
+            }
+            None => Err(Error::transport(UnmatchedRequest {
+                method: wanted.method,
+                url: wanted.url,
+            })),
+        };
+        Box::pin(futures::future::ready(res))
+    }
+}
+
+fn is_scrubbed_key(key: &str) -> bool {
+    SCRUBBED_KEYS.contains(&key)
+}
+
+/// Scrubs secrets from a JSON or form-encoded body. Anything else is recorded as-is.
+fn scrub_body(body: Vec<u8>) -> RecordedBody {
+    if let Ok(mut v) = serde_json::from_slice::<Value>(&body) {
+        scrub_json(&mut v);
+        return RecordedBody::Text(v.to_string());
+    }
+
+    match RecordedBody::from_bytes(body) {
+        RecordedBody::Text(s) if s.contains('=') && !s.contains(char::is_whitespace) => {
+            let scrubbed: Vec<String> = s.split('&')
+                .map(|pair| match pair.split_once('=') {
+                    Some((k, _)) if is_scrubbed_key(k) => format!("{}={}", k, REDACTED),
+                    _ => pair.to_owned(),
+                })
+                .collect();
+            RecordedBody::Text(scrubbed.join("&"))
+        }
+        other => other,
+    }
+}
+
+fn scrub_json(v: &mut Value) {
+    match v {
+        Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                if is_scrubbed_key(k) {
+                    *v = Value::String(REDACTED.into());
+                } else {
+                    scrub_json(v);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(scrub_json),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+
+    /// A transport which always hands out a token, standing in for the real servers.
+    #[derive(Debug)]
+    struct TokenServer;
+
+    impl Transport for TokenServer {
+        fn execute(&self, _request: Request) -> BoxFuture<'_, Result<Response, Error>> {
+            let body = br#"{"access_token":"hunter2","token_type":"Bearer"}"#.to_vec();
+            let headers = [("Set-Cookie".to_owned(), "session=abc".to_owned())];
+            Box::pin(futures::future::ready(build_response(200, &headers, body)))
+        }
+    }
+
+    #[tokio::test]
+    async fn record_then_replay() {
+        let recorder = RecordingTransport::new(TokenServer);
+        let client = Client::builder()
+            .transport(recorder.clone())
+            .build_with_credentials("my_id", "my_secret")
+            .await
+            .unwrap();
+        assert_eq!(client.bearer_token(), "Bearer hunter2");
+
+        let cassette = recorder.cassette();
+        let recorded = serde_json::to_string(&cassette).unwrap();
+        assert!(!recorded.contains("hunter2"));
+        assert!(!recorded.contains("my_secret"));
+        assert!(!recorded.contains("session=abc"));
+
+        let replayer = ReplayTransport::new(cassette);
+        let client = Client::builder()
+            .transport(replayer)
+            .build_with_credentials("my_id", "another_secret")
+            .await
+            .unwrap();
+        assert_eq!(client.bearer_token(), format!("Bearer {}", REDACTED));
+    }
+
+    #[tokio::test]
+    async fn replay_miss_is_an_error() {
+        let res = Client::builder()
+            .transport(ReplayTransport::new(Cassette::new()))
+            .build_with_credentials("my_id", "my_secret")
+            .await;
+        assert!(matches!(res, Err(Error::Transport(_))));
+    }
+}
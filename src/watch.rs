@@ -0,0 +1,437 @@
+// Copyright 2020 Nick Samson -- See LICENSE for copyright info.
+
+//! Contains the [Watcher], which polls stories and authors for updates and reports them as a
+//! stream of [WatchEvent]s, e.g. for notification bots.
+//!
+//! ```no_run
+//! # async fn run(client: fimapi::client::Client) {
+//! use fimapi::watch::{WatchEvent, Watcher};
+//! use futures::StreamExt;
+//! use std::time::Duration;
+//!
+//! let mut events = Box::pin(Watcher::new(client)
+//!     .story(1)
+//!     .author(2)
+//!     .interval(Duration::from_secs(600))
+//!     .into_stream());
+//!
+//! while let Some(event) = events.next().await {
+//!     match event {
+//!         Ok(WatchEvent::NewChapter { story, chapter }) => {
+//!             println!("{} has a new chapter: {}", story.attributes.title, chapter.attributes.title)
+//!         }
+//!         Ok(_) => {}
+//!         Err(e) => eprintln!("poll failed: {}", e),
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::client::Client;
+use crate::model::{Chapter, CompletionStatus, Story};
+use crate::response::Error;
+use crate::sync::{sync, Change, StoryState, SyncState};
+use futures::Stream;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The default time between polls.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Something that happened to a watched story.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum WatchEvent {
+    /// A watched author published a new story.
+    NewStory(Story),
+    /// A chapter was added to a story.
+    NewChapter {
+        /// The story, as it is now.
+        story: Story,
+        /// The new chapter, including its text.
+        chapter: Box<Chapter>,
+    },
+    /// A story was modified in any way.
+    StoryUpdated(Story),
+    /// A story was marked as complete.
+    StoryCompleted(Story),
+    /// A watched story could not be checked, e.g. because it was made private or the request
+    /// was rate limited. The other stories are still checked, and this one is tried again on
+    /// the next poll.
+    Failed {
+        /// The id of the story.
+        story_id: u64,
+        /// Why the story could not be checked.
+        error: Arc<Error>,
+    },
+}
+
+/// Polls a set of stories and authors on an interval and reports what changed.
+///
+/// The first successful poll only records the current state of everything watched; events are
+/// reported from the next poll on. Rate limited requests are retried by the client's
+/// [RetryPolicy][crate::retry::RetryPolicy], and if a poll still fails, the next one waits
+/// for as long as the server asked.
+#[derive(Debug, Clone)]
+pub struct Watcher {
+    client: Client,
+    stories: BTreeSet<u64>,
+    authors: BTreeSet<u64>,
+    interval: Duration,
+}
+
+impl Watcher {
+    /// Creates a watcher which watches nothing, polling every [DEFAULT_INTERVAL].
+    pub fn new(client: Client) -> Self {
+        Watcher {
+            client,
+            stories: BTreeSet::new(),
+            authors: BTreeSet::new(),
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+
+    /// Watches the story with the given id.
+    pub fn story(mut self, id: u64) -> Self {
+        self.stories.insert(id);
+        self
+    }
+
+    /// Watches every story by the user with the given id, including stories published later.
+    pub fn author(mut self, id: u64) -> Self {
+        self.authors.insert(id);
+        self
+    }
+
+    /// Sets the time between polls.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Starts watching. Polls happen while the stream is polled, so nothing is requested until
+    /// the first event is awaited. Stories which fail to load are reported as
+    /// [WatchEvent::Failed]; polls which fail as a whole, e.g. because the token was revoked,
+    /// are reported as errors. Neither ends the stream.
+    pub fn into_stream(self) -> impl Stream<Item = Result<WatchEvent, Error>> {
+        let state = WatchState {
+            watcher: self,
+            sync: SyncState::new(),
+            statuses: BTreeMap::new(),
+            pending: VecDeque::new(),
+            polls: 0,
+            baseline_done: false,
+            wait: None,
+        };
+        futures::stream::unfold(state, |mut state| async move {
+            loop {
+                if let Some(event) = state.pending.pop_front() {
+                    return Some((Ok(event), state));
+                }
+                if state.polls > 0 {
+                    let wait = state.wait.take().unwrap_or(state.watcher.interval);
+                    tokio::time::delay_for(wait).await;
+                }
+                state.polls += 1;
+                if let Err(e) = state.poll().await {
+                    state.wait = e.retry_after().map(|d| d.max(state.watcher.interval));
+                    return Some((Err(e), state));
+                }
+            }
+        })
+    }
+}
+
+struct WatchState {
+    watcher: Watcher,
+    sync: SyncState,
+    statuses: BTreeMap<u64, Option<CompletionStatus>>,
+    pending: VecDeque<WatchEvent>,
+    polls: u64,
+    /// Whether a poll has succeeded, so that later polls report events.
+    baseline_done: bool,
+    wait: Option<Duration>,
+}
+
+impl WatchState {
+    async fn poll(&mut self) -> Result<(), Error> {
+        let client = &self.watcher.client;
+        let baseline = !self.baseline_done;
+
+        // Nothing is recorded until the whole poll succeeds, so a failed poll is repeated in
+        // full and reports the same events.
+        let mut new_stories = Vec::new();
+        for author in &self.watcher.authors {
+            for story in client.user_stories(*author).await? {
+                let id = match story.id.parse::<u64>() {
+                    Ok(id) => id,
+                    Err(_) => continue,
+                };
+                if !self.watcher.stories.contains(&id) && !new_stories.iter().any(|(s, _)| *s == id) {
+                    new_stories.push((id, story));
+                }
+            }
+        }
+
+        // Stories seen for the first time only have their state recorded.
+        let known = self.sync.story_ids();
+        let unseen: Vec<u64> = self.watcher.stories.iter()
+            .chain(new_stories.iter().map(|(id, _)| id))
+            .filter(|id| !self.sync.stories.contains_key(id))
+            .copied()
+            .collect();
+        let mut recorded = Vec::new();
+        let mut failed = BTreeMap::new();
+        for id in unseen {
+            let loaded = match client.story(id).await {
+                Ok(story) => client.story_chapters(id).await.map(|chapters| (story, chapters)),
+                Err(e) => Err(e),
+            };
+            match loaded {
+                Ok((story, chapters)) => {
+                    recorded.push((id, story.attributes.completion_status, StoryState::from_resources(&story, &chapters)))
+                }
+                Err(e) if e.is_auth_error() => return Err(e),
+                Err(e) => {
+                    failed.insert(id, e);
+                }
+            }
+        }
+
+        let mut diff = sync(client, &self.sync, known).await?;
+        for (id, status, state) in recorded {
+            self.statuses.insert(id, status);
+            self.sync.stories.insert(id, state);
+        }
+        for (id, story) in new_stories {
+            // A new story which failed to load is reported as new once it does.
+            if baseline {
+                self.watcher.stories.insert(id);
+            } else if !failed.contains_key(&id) {
+                self.watcher.stories.insert(id);
+                self.pending.push_back(WatchEvent::NewStory(story));
+            }
+        }
+        for change in &diff.stories {
+            let story = match change {
+                Change::Updated(story) => story,
+                Change::Removed { id } => {
+                    if let Ok(id) = id.parse::<u64>() {
+                        self.watcher.stories.remove(&id);
+                        self.statuses.remove(&id);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let id = match story.id.parse::<u64>() {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+
+            self.pending.push_back(WatchEvent::StoryUpdated(story.clone()));
+            for (_, chapter) in diff.chapters.iter().filter(|(s, _)| *s == id) {
+                if let Change::Added(chapter) = chapter {
+                    self.pending.push_back(WatchEvent::NewChapter { story: story.clone(), chapter: Box::new(chapter.clone()) });
+                }
+            }
+
+            let status = story.attributes.completion_status;
+            let previous = self.statuses.insert(id, status).flatten();
+            if status == Some(CompletionStatus::Complete) && previous != Some(CompletionStatus::Complete) {
+                self.pending.push_back(WatchEvent::StoryCompleted(story.clone()));
+            }
+        }
+        self.sync.apply(&diff);
+        self.baseline_done = true;
+
+        // Stories which failed are retried next poll, but every failure is still reported.
+        failed.append(&mut diff.failed);
+        for (story_id, error) in failed {
+            self.pending.push_back(WatchEvent::Failed { story_id, error: Arc::new(error) });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fixtures, MockClient};
+    use futures::StreamExt;
+
+    const NEW_CHAPTER: &str = r#"{
+        "data": {
+            "id": "13",
+            "type": "chapter",
+            "attributes": {
+                "chapter_number": 3,
+                "title": "The Epilogue",
+                "date_modified": "2020-07-01T12:00:00+00:00",
+                "content": "Fin."
+            }
+        }
+    }"#;
+
+    fn updated_story() -> String {
+        fixtures::STORY
+            .replace("2020-06-01T12:00:00+00:00", "2020-07-01T12:00:00+00:00")
+            .replace(r#""completion_status": "incomplete""#, r#""completion_status": "complete""#)
+    }
+
+    fn updated_chapters() -> String {
+        let listing: serde_json::Value = serde_json::from_str(fixtures::CHAPTERS).unwrap();
+        let new: serde_json::Value = serde_json::from_str(NEW_CHAPTER).unwrap();
+        let mut data = listing["data"].as_array().unwrap().clone();
+        data.push(new["data"].clone());
+        serde_json::json!({ "data": data }).to_string()
+    }
+
+    #[tokio::test]
+    async fn reports_updates_after_the_first_poll() {
+        let mock = MockClient::new();
+        mock.on_get("/stories/1", fixtures::STORY)
+            .on_get("/stories/1", updated_story())
+            .on_get("/stories/1/chapters", fixtures::CHAPTERS)
+            .on_get("/stories/1/chapters", updated_chapters())
+            .on_get("/chapters/13", NEW_CHAPTER);
+
+        let events: Vec<WatchEvent> = Watcher::new(mock.client())
+            .story(1)
+            .interval(Duration::from_millis(1))
+            .into_stream()
+            .take(3)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+
+        assert!(matches!(&events[0], WatchEvent::StoryUpdated(s) if s.id == "1"));
+        assert!(matches!(&events[1], WatchEvent::NewChapter { chapter, .. } if chapter.attributes.title == "The Epilogue"));
+        assert!(matches!(&events[2], WatchEvent::StoryCompleted(_)));
+    }
+
+    #[tokio::test]
+    async fn reports_new_stories_by_authors() {
+        let story = |id: u64| format!(r#"{{"id":"{}","type":"story","attributes":{{"title":"Story {}"}}}}"#, id, id);
+        let mock = MockClient::new();
+        mock.on_get("/stories?filter[author]=2", format!(r#"{{"data":[{}]}}"#, story(1)))
+            .on_get("/stories?filter[author]=2", format!(r#"{{"data":[{},{}]}}"#, story(1), story(3)))
+            .on_get("/stories/1", format!(r#"{{"data":{}}}"#, story(1)))
+            .on_get("/stories/3", format!(r#"{{"data":{}}}"#, story(3)))
+            .on_get("/stories/1/chapters", r#"{"data":[]}"#)
+            .on_get("/stories/3/chapters", r#"{"data":[]}"#);
+
+        let mut events = Box::pin(Watcher::new(mock.client())
+            .author(2)
+            .interval(Duration::from_millis(1))
+            .into_stream());
+        match events.next().await {
+            Some(Ok(WatchEvent::NewStory(s))) => assert_eq!(s.attributes.title, "Story 3"),
+            other => panic!("expected a new story, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn the_first_successful_poll_is_the_baseline() {
+        let story = |id: u64| format!(r#"{{"id":"{}","type":"story","attributes":{{"title":"Story {}"}}}}"#, id, id);
+        let mock = MockClient::new();
+        mock.on("GET", "/stories?filter[author]=2", 500, "oops")
+            .on_get("/stories?filter[author]=2", format!(r#"{{"data":[{}]}}"#, story(1)))
+            .on_get("/stories?filter[author]=2", format!(r#"{{"data":[{},{}]}}"#, story(1), story(3)))
+            .on_get("/stories/1", format!(r#"{{"data":{}}}"#, story(1)))
+            .on_get("/stories/3", format!(r#"{{"data":{}}}"#, story(3)))
+            .on_get("/stories/1/chapters", r#"{"data":[]}"#)
+            .on_get("/stories/3/chapters", r#"{"data":[]}"#);
+
+        let mut events = Box::pin(Watcher::new(mock.client())
+            .author(2)
+            .interval(Duration::from_millis(1))
+            .into_stream());
+        assert!(matches!(events.next().await, Some(Err(Error::Server { .. }))));
+        match events.next().await {
+            Some(Ok(WatchEvent::NewStory(s))) => assert_eq!(s.attributes.title, "Story 3"),
+            other => panic!("expected a new story, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_polls_do_not_lose_new_stories() {
+        let story = |id: u64| format!(r#"{{"id":"{}","type":"story","attributes":{{"title":"Story {}"}}}}"#, id, id);
+        let mock = MockClient::new();
+        mock.on_get("/stories?filter[author]=2", format!(r#"{{"data":[{}]}}"#, story(1)))
+            .on_get("/stories?filter[author]=2", format!(r#"{{"data":[{},{}]}}"#, story(1), story(3)))
+            .on_get("/stories/1", format!(r#"{{"data":{}}}"#, story(1)))
+            .on("GET", "/stories/3", 500, "oops")
+            .on_get("/stories/3", format!(r#"{{"data":{}}}"#, story(3)))
+            .on_get("/stories/1/chapters", r#"{"data":[]}"#)
+            .on_get("/stories/3/chapters", r#"{"data":[]}"#);
+
+        let mut events = Box::pin(Watcher::new(mock.client())
+            .author(2)
+            .interval(Duration::from_millis(1))
+            .into_stream());
+        let mut failed = false;
+        loop {
+            match events.next().await {
+                Some(Ok(WatchEvent::Failed { story_id: 3, error })) => {
+                    assert!(matches!(*error, Error::Server { .. }));
+                    failed = true;
+                }
+                Some(Ok(WatchEvent::NewStory(s))) => {
+                    assert_eq!(s.attributes.title, "Story 3");
+                    break;
+                }
+                Some(Ok(_)) => {}
+                other => panic!("expected a new story, got {:?}", other),
+            }
+        }
+        assert!(failed);
+    }
+
+    #[tokio::test]
+    async fn failed_stories_do_not_stop_the_others() {
+        let story = |id: u64| format!(r#"{{"id":"{}","type":"story","attributes":{{"title":"Story {}"}}}}"#, id, id);
+        let mock = MockClient::new();
+        mock.on_get("/stories/1", fixtures::STORY)
+            .on_get("/stories/1", updated_story())
+            .on_get("/stories/1/chapters", fixtures::CHAPTERS)
+            .on_get("/stories/1/chapters", updated_chapters())
+            .on_get("/chapters/13", NEW_CHAPTER)
+            .on("GET", "/stories/2", 404, "gone")
+            .on_get("/stories/3", format!(r#"{{"data":{}}}"#, story(3)))
+            .on("GET", "/stories/3", 500, "oops")
+            .on_get("/stories/3/chapters", r#"{"data":[]}"#);
+
+        let events: Vec<WatchEvent> = Watcher::new(mock.client())
+            .story(1)
+            .story(2)
+            .story(3)
+            .interval(Duration::from_millis(1))
+            .into_stream()
+            .take(6)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+
+        // The first poll can't load story 2; the second can't load 2 or 3, but still sees 1.
+        assert!(matches!(&events[0], WatchEvent::Failed { story_id: 2, .. }));
+        assert!(matches!(&events[1], WatchEvent::StoryUpdated(s) if s.id == "1"));
+        assert!(matches!(&events[2], WatchEvent::NewChapter { .. }));
+        assert!(matches!(&events[3], WatchEvent::StoryCompleted(_)));
+        assert!(matches!(&events[4], WatchEvent::Failed { story_id: 2, .. }));
+        assert!(matches!(&events[5], WatchEvent::Failed { story_id: 3, error } if matches!(**error, Error::Server { .. })));
+    }
+
+    #[tokio::test]
+    async fn auth_errors_fail_the_poll() {
+        let mock = MockClient::new();
+        mock.on("GET", "/stories/1", 403, r#"{"errors":[{"status":"403","code":4032}]}"#);
+
+        let mut events = Box::pin(Watcher::new(mock.client())
+            .story(1)
+            .interval(Duration::from_millis(1))
+            .into_stream());
+        assert!(matches!(events.next().await, Some(Err(e)) if e.is_auth_error()));
+    }
+}